@@ -1,12 +1,19 @@
 use crate::ctx::Sort;
 
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Symbol {
     BoolLit(bool),
     IntLit(i64),
+    StrLit(String),
+    // an array value: its sort, the default element, and the stored (index, element) pairs
+    ArrayLit(Sort, Box<Symbol>, Vec<(Symbol, Symbol)>),
+    // a datatype value: its sort, its constructor, and the values of its fields
+    DtLit(Sort, String, Vec<Symbol>),
+    // (as const S) applied to its default element
+    ConstArray(Sort),
     Func(String),
     NonTerm(Sort, String),
 }
@@ -16,19 +23,53 @@ impl Symbol {
         match name {
             "true" => Symbol::BoolLit(true),
             "false" => Symbol::BoolLit(false),
+            // string literals escape double quotes by doubling them
+            _ if name.len() > 1 && name.starts_with('"') && name.ends_with('"') => {
+                Symbol::StrLit(name[1..name.len()-1].replace("\"\"", "\""))
+            }
             _ => match name.parse::<i64>() {
                 Ok(v) => Symbol::IntLit(v),
                 Err(_) => Symbol::Func(name.to_owned())
             }
         }
     }
+
+    pub fn mk_array(asort: Sort, default: Symbol, entries: Vec<(Symbol, Symbol)>) -> Symbol {
+        // keep array values canonical so that = can compare them structurally
+        let mut canonical: Vec<(Symbol, Symbol)> = vec![];
+        for (i, e) in entries {
+            canonical.retain(|(j, _)| j != &i);
+            if e != default {
+                canonical.push((i, e));
+            }
+        }
+        canonical.sort();
+        Symbol::ArrayLit(asort, Box::new(default), canonical)
+    }
 }
 
 impl fmt::Display for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match self {
             Symbol::BoolLit(b) => b.to_string(),
+            // smt-lib has no negative literals
+            Symbol::IntLit(i) if *i < 0 => format!("(- {})", i.unsigned_abs()),
             Symbol::IntLit(i) => i.to_string(),
+            Symbol::StrLit(s) => format!("\"{}\"", s.replace("\"", "\"\"")),
+            Symbol::ArrayLit(s, d, entries) => {
+                let mut printable = format!("((as const {}) {})", s, d);
+                for (i, e) in entries {
+                    printable = format!("(store {} {} {})", printable, i, e);
+                }
+                printable
+            },
+            Symbol::DtLit(_, c, fields) => if fields.is_empty() {
+                c.clone()
+            } else {
+                let fields : Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+                format!("({} {})", c, fields.join(" "))
+            },
+            Symbol::ConstArray(s) => format!("(as const {})", s),
             Symbol::Func(s) => s.clone(),
             Symbol::NonTerm(_, n) => format!("?{}?", n),
         };
@@ -42,28 +83,29 @@ impl fmt::Debug for Symbol {
     }
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Term {
     symbol: Symbol,
-    args: Vec<Rc<Term>>,
+    args: Vec<Arc<Term>>,
 }
 
 impl Term {
-    pub fn new(symbol: Symbol, args: Vec<Rc<Term>>) -> Rc<Term> {
-        Rc::new(Term {
+    pub fn new(symbol: Symbol, args: Vec<Arc<Term>>) -> Arc<Term> {
+        Arc::new(Term {
             symbol: symbol,
             args: args
         })
     }
 
-    pub fn mk_const(symbol: Symbol) -> Rc<Term> {
+    pub fn mk_const(symbol: Symbol) -> Arc<Term> {
         Term::new(symbol, vec! [])
     }
 
-    pub fn mk_app(symbol: Symbol, args: Vec<Rc<Term>>) -> Rc<Term> {
+    pub fn mk_app(symbol: Symbol, args: Vec<Arc<Term>>) -> Arc<Term> {
         Term::new(symbol, args)
     }
 
-    pub fn get_args(&self) -> std::slice::Iter<Rc<Term>> {
+    pub fn get_args(&self) -> std::slice::Iter<Arc<Term>> {
         self.args.iter()
     }
 
@@ -77,6 +119,12 @@ impl Term {
             _ => self.args.iter().fold(true, |acc, a| acc && a.is_terminated())
         }
     }
+
+    // does the term apply the function anywhere
+    pub fn calls(&self, name: &str) -> bool {
+        matches!(&self.symbol, Symbol::Func(f) if f == name && !self.args.is_empty())
+            || self.args.iter().any(|a| a.calls(name))
+    }
 }
 
 impl fmt::Display for Term {
@@ -98,7 +146,7 @@ impl fmt::Debug for Term {
 
 #[cfg(test)]
 mod test {
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     use crate::qry::Query;
 
@@ -110,7 +158,7 @@ mod test {
         q.declare_const("y", "Int");
         let x = q.mk_const("x");
         let y = q.mk_const("y");
-        let plus = q.mk_add(Rc::clone(&x), Rc::clone(&y));
+        let plus = q.mk_add(Arc::clone(&x), Arc::clone(&y));
         assert_eq!("(+ x y)", format!("{}", plus));
     }
 }
\ No newline at end of file
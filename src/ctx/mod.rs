@@ -1,47 +1,109 @@
-use std::collections::{HashMap};
-use multimap::MultiMap;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map;
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::ast::{Term, Symbol};
 
 pub type Signature = (Vec<(String, Sort)>, Sort);
-pub type Solution = HashMap<String, Rc<Term>>;
+pub type Solution = HashMap<String, Arc<Term>>;
+// a datatype constructor: its name and its (selector, sort) fields
+pub type Constructor = (String, Vec<(String, Sort)>);
 
+#[derive(Clone)]
 pub struct Context {
-    symbol_tbl: MultiMap<String, Signature>,
+    symbol_tbl: HashMap<String, Signature>,
+    builtin_tbl: BTreeMap<String, Builtin>,
     body_tbl: Solution,
+    datatype_tbl: Vec<(String, Vec<Constructor>)>,
     logic: Logic,
+    scopes: Vec<Scope>,
+}
+
+// what a pop has to restore
+#[derive(Clone)]
+struct Scope {
+    symbol_tbl: HashMap<String, Signature>,
+    body_tbl: Solution,
+    datatype_tbl: Vec<(String, Vec<Constructor>)>,
 }
 
 impl Context {
     pub fn new() -> Context {
         let mut ctx = Context {
-            symbol_tbl: MultiMap::new(),
+            symbol_tbl: HashMap::new(),
+            builtin_tbl: BTreeMap::new(),
             body_tbl: HashMap::new(),
+            datatype_tbl: vec![],
             logic: Logic::new(),
+            scopes: vec![],
         };
         ctx.add_booleans();
         ctx
     }
 
-    pub fn get_decl(&self, name: &str) -> Option<&Vec<Signature>> {
-        // some interpreted functions are polymorphic (e.g. =)
-        self.symbol_tbl.get_vec(name)
+    // an empty context with the same logic and datatypes, for checking and evaluating bodies
+    pub fn fresh(&self) -> Context {
+        let mut ctx = Context::new();
+        ctx.update_logic(self.get_logic());
+        if !self.datatype_tbl.is_empty() {
+            ctx.add_datatypes(self.datatype_tbl.clone());
+        }
+        ctx
+    }
+
+    pub fn push(&mut self) {
+        self.scopes.push(Scope {
+            symbol_tbl: self.symbol_tbl.clone(),
+            body_tbl: self.body_tbl.clone(),
+            datatype_tbl: self.datatype_tbl.clone(),
+        });
+    }
+
+    pub fn pop(&mut self) {
+        let scope = self.scopes.pop().expect("no scope to pop");
+        self.symbol_tbl = scope.symbol_tbl;
+        self.body_tbl = scope.body_tbl;
+        self.datatype_tbl = scope.datatype_tbl;
+    }
+
+    // the same context where the parameters shadow anything of the same name, for checking bodies
+    // that may call other definitions
+    pub fn with_params(&self, params: &[(String, Sort)]) -> Context {
+        let mut ctx = self.clone();
+        for (n, s) in params {
+            ctx.body_tbl.remove(n);
+            ctx.symbol_tbl.insert(n.clone(), (vec![], s.clone()));
+        }
+        ctx
+    }
+
+    pub fn get_decl(&self, name: &str) -> Option<&Signature> {
+        self.symbol_tbl.get(name)
     }
 
-    pub fn get_decls(&self) -> multimap::Iter<String, Signature> {
+    pub fn get_decls(&self) -> hash_map::Iter<'_, String, Signature> {
         self.symbol_tbl.iter()
     }
 
+    // interpreted symbols live in their own table since they can be polymorphic (e.g. =)
+    pub fn get_builtin(&self, name: &str) -> Option<&Builtin> {
+        self.builtin_tbl.get(name)
+    }
+
+    pub fn get_builtins(&self) -> std::collections::btree_map::Iter<'_, String, Builtin> {
+        self.builtin_tbl.iter()
+    }
+
     pub fn add_decl(&mut self, name: &str, params: Vec<(String, Sort)>, rsort: Sort) {
         // can declare each function exactly once
         // debug!("ctx declaring {}", name);
         assert!(!self.symbol_tbl.contains_key(name), "{} already in symbol table", name);
+        assert!(!self.builtin_tbl.contains_key(name), "{} is interpreted", name);
         assert!(self.logic.uf || params.len() == 0);
-        assert!(! (rsort == Sort::Int) || self.logic.lia);
+        assert!(self.supports(&rsort), "sort {} not supported", rsort);
         for (_, s) in params.iter() {
-            assert!(! (s == &Sort::Int) || self.logic.lia);
+            assert!(self.supports(s), "sort {} not supported", s);
         }
         self.symbol_tbl.insert(name.to_owned(), (params, rsort));
     }
@@ -49,22 +111,91 @@ impl Context {
     pub fn add_synth(&mut self, name: &str, params: Vec<(String, Sort)>, rsort: Sort) {
         // can declare each function exactly once
         assert!(!self.symbol_tbl.contains_key(name), "{} already in symbol table", name);
+        assert!(!self.builtin_tbl.contains_key(name), "{} is interpreted", name);
         self.symbol_tbl.insert(name.to_owned(), (params, rsort));
     }
 
-    pub fn get_body(&self, name: &str) -> Option<&Rc<Term>> {
+    pub fn add_datatypes(&mut self, datatypes: Vec<(String, Vec<Constructor>)>) {
+        assert!(self.logic.dt, "datatypes not supported");
+        // declare all the sorts first: datatypes can be mutually recursive
+        for (name, _) in datatypes.iter() {
+            assert!(self.get_datatype(name).is_none(), "{} already declared", name);
+            self.datatype_tbl.push((name.clone(), vec![]));
+        }
+        for (name, ctors) in datatypes {
+            let dsort = Sort::Datatype(name.clone());
+            for (cname, fields) in ctors.iter() {
+                assert!(!self.symbol_tbl.contains_key(cname), "{} already in symbol table", cname);
+                for (sname, fsort) in fields {
+                    assert!(self.supports(fsort), "sort {} not supported", fsort);
+                    assert!(!self.symbol_tbl.contains_key(sname), "{} already in symbol table", sname);
+                    self.symbol_tbl.insert(sname.clone(), (vec![("x".to_owned(), dsort.clone())], fsort.clone()));
+                }
+                self.symbol_tbl.insert(cname.clone(), (fields.clone(), dsort.clone()));
+                self.symbol_tbl.insert(format!("is-{}", cname), (vec![("x".to_owned(), dsort.clone())], Sort::Bool));
+            }
+            let entry = self.datatype_tbl.iter_mut().find(|(n, _)| n == &name).expect("unreachable");
+            entry.1 = ctors;
+        }
+    }
+
+    pub fn get_datatype(&self, name: &str) -> Option<&Vec<Constructor>> {
+        self.datatype_tbl.iter().find(|(n, _)| n == name).map(|(_, ctors)| ctors)
+    }
+
+    // the datatype and fields of a constructor
+    pub fn get_constructor(&self, name: &str) -> Option<(Sort, &Vec<(String, Sort)>)> {
+        for (dname, ctors) in self.datatype_tbl.iter() {
+            for (cname, fields) in ctors {
+                if cname == name {
+                    return Some((Sort::Datatype(dname.clone()), fields))
+                }
+            }
+        }
+        None
+    }
+
+    // the constructor a selector belongs to, the position of its field, and the field sort
+    pub fn get_selector(&self, name: &str) -> Option<(&String, usize, &Sort)> {
+        for (_, ctors) in self.datatype_tbl.iter() {
+            for (cname, fields) in ctors {
+                if let Some(idx) = fields.iter().position(|(s, _)| s == name) {
+                    return Some((cname, idx, &fields[idx].1))
+                }
+            }
+        }
+        None
+    }
+
+    // the constructor a tester checks for
+    pub fn get_tester(&self, name: &str) -> Option<&String> {
+        let cname = name.strip_prefix("is-")?;
+        self.datatype_tbl.iter().flat_map(|(_, ctors)| ctors.iter()).map(|(c, _)| c).find(|c| c.as_str() == cname)
+    }
+
+    pub fn supports(&self, s: &Sort) -> bool {
+        match s {
+            Sort::Bool => true,
+            Sort::Int => self.logic.lia,
+            Sort::Array(i, e) => self.logic.a && self.supports(i) && self.supports(e),
+            Sort::String => self.logic.s,
+            Sort::Datatype(n) => self.logic.dt && self.get_datatype(n).is_some(),
+        }
+    }
+
+    pub fn get_body(&self, name: &str) -> Option<&Arc<Term>> {
         self.body_tbl.get(name)
     }
     
-    pub fn add_body(&mut self, name: &str, body: Rc<Term>) {
-        // the new body has to be associated to exactly one function
-        assert_eq!(self.symbol_tbl.get_vec(name).unwrap().len(), 1);
+    pub fn add_body(&mut self, name: &str, body: Arc<Term>) {
+        // the new body has to be associated to a declared function
+        assert!(self.symbol_tbl.contains_key(name), "{} not declared", name);
         self.body_tbl.insert(name.to_owned(), body);
     }
 
     pub fn remove_body(&mut self, name: &str) {
-        // the new body has to be associated to exactly one function
-        assert_eq!(self.symbol_tbl.get_vec(name).unwrap().len(), 1);
+        // the body has to be associated to a declared function
+        assert!(self.symbol_tbl.contains_key(name), "{} not declared", name);
         self.body_tbl.remove(&name.to_owned());
     }
 
@@ -79,6 +210,17 @@ impl Context {
         if l.uf {
             self.logic.uf = true;
         }
+        if l.a {
+            self.logic.a = true;
+            self.add_arrays();
+        }
+        if l.dt {
+            self.logic.dt = true;
+        }
+        if l.s {
+            self.logic.s = true;
+            self.add_strings();
+        }
     }
 
     pub fn get_logic(&self) -> &Logic {
@@ -86,148 +228,358 @@ impl Context {
     }
 
     // shallow version of check_sort
-    pub fn get_sort(&self, t: &Rc<Term>) -> Option<Sort> {
+    pub fn get_sort(&self, t: &Term) -> Option<Sort> {
         match t.get_symbol() {
             Symbol::Func(s) => {
-                match self.get_decl(s) {
-                    Some(v) => {
-                        if v.len() == 1 {
-                            let (_, rsort) = v[0];
-                            Some(rsort)
-                        } else {
-                            // we have to figure out which version of the polymorphic operator we're dealing with
-                            let args: Vec<&Rc<Term>> = t.get_args().collect();
-                            for (params, rsort) in v {
-                                if args.len() == params.len() {
-                                    let mut matches = true;
-                                    for i in 0..params.len() {
-                                        matches = matches && match self.get_sort(args[i]) {
-                                            Some(r) => r == params[i].1,
-                                            None => false
-                                        }
-                                    }
-                                    if matches {
-                                        return Some(*rsort)
-                                    }
-                                }
-                            }
-                            None
+                match self.get_builtin(s) {
+                    // only look at the arguments if the result sort depends on them
+                    Some(f) => match &f.rsort {
+                        Pattern::Is(rsort) => Some(rsort.clone()),
+                        _ => {
+                            let arg_sorts: Option<Vec<Sort>> = t.get_args().map(|a| self.get_sort(a)).collect();
+                            f.apply(&arg_sorts?)
                         }
-                    }
-                    None => None                    
+                    },
+                    None => self.get_decl(s).map(|(_, rsort)| rsort.clone())
                 }
             },
             Symbol::BoolLit(_) => Some(Sort::Bool),
             Symbol::IntLit(_) => Some(Sort::Int),
-            Symbol::NonTerm(s, _) => Some(*s),
+            Symbol::StrLit(_) => Some(Sort::String),
+            Symbol::ArrayLit(s, _, _) => Some(s.clone()),
+            Symbol::DtLit(s, _, _) => Some(s.clone()),
+            Symbol::ConstArray(s) => Some(s.clone()),
+            Symbol::NonTerm(s, _) => Some(s.clone()),
         }
     }
 
-    pub fn check_sort(&self, t: &Rc<Term>) -> Option<Sort> {
+    pub fn check_sort(&self, t: &Arc<Term>) -> Option<Sort> {
+        // none if any argument is not well-formed
         let arg_sorts: Vec<Sort> = t.get_args()
             .inspect(|x| debug!("checking {}", x))
-            .map(|a| self.check_sort(a)
-            .expect("term not well-formed!"))
-            .collect();
+            .map(|a| self.check_sort(a))
+            .collect::<Option<Vec<Sort>>>()?;
 
         match t.get_symbol() {
             Symbol::Func(s) => {
-                match self.get_decl(s) {
-                    Some(v) => {
-                        for (params, rsort) in v {
-                            let exp_sorts: Vec<&Sort> = params.into_iter().map(|(_, s)| s).collect();
-                            if exp_sorts.len() != arg_sorts.len() {
-                                continue
-                            };
-                            let mut result = true;
-                            for i in 0..arg_sorts.len() {
-                                result = result && exp_sorts[i] == &arg_sorts[i];
-                            }
-                            if result {
-                                debug!("name: {} rsort: {}", t.get_symbol(), rsort);
-                                return Some(*rsort)
-                            }  
+                match (self.get_builtin(s), self.get_decl(s)) {
+                    (Some(f), _) => f.apply(&arg_sorts),
+                    (None, Some((params, rsort))) => {
+                        let exp_sorts: Vec<&Sort> = params.iter().map(|(_, s)| s).collect();
+                        if exp_sorts.len() == arg_sorts.len() && exp_sorts.iter().zip(arg_sorts.iter()).all(|(e, a)| *e == a) {
+                            debug!("name: {} rsort: {}", t.get_symbol(), rsort);
+                            Some(rsort.clone())
+                        } else {
+                            None
                         }
-                        None
                     }
-                    None => None                    
+                    (None, None) => None
                 }
             },
             Symbol::BoolLit(_) => Some(Sort::Bool),
             Symbol::IntLit(_) => Some(Sort::Int),
-            Symbol::NonTerm(s, _) => Some(*s),
+            Symbol::StrLit(_) => Some(Sort::String),
+            Symbol::ArrayLit(s, _, _) => Some(s.clone()),
+            Symbol::DtLit(s, _, _) => Some(s.clone()),
+            Symbol::ConstArray(s) => match s {
+                Sort::Array(_, e) if arg_sorts.len() == 1 && arg_sorts[0] == **e => Some(s.clone()),
+                _ => None
+            },
+            Symbol::NonTerm(s, _) => Some(s.clone()),
         }
     }
 
+    fn add_builtin(&mut self, name: &str, params: Vec<Pattern>, rsort: Pattern, kind: Kind) {
+        self.builtin_tbl.insert(name.to_owned(), Builtin { params, rsort, kind, props: Props::default() });
+    }
+
+    fn set_props(&mut self, name: &str, props: Props) {
+        self.builtin_tbl.get_mut(name).expect("unknown builtin").props = props;
+    }
+
     fn add_booleans(&mut self) {
-        // only support upto 5-ary
-        for op in vec! ["and", "or", "="] {
-            for names in vec! [vec! ["a", "b"], vec! ["a", "b"], vec! ["a", "b", "c"], vec! ["a", "b", "c", "d"], vec! ["a", "b", "c", "d", "e"]] {
-                let params = names.into_iter().map(|n| (n.to_owned(), Sort::Bool)).collect();
-                self.symbol_tbl.insert(op.to_owned(), (params, Sort::Bool));
-            }
+        let bool = || Pattern::Is(Sort::Bool);
+        self.add_builtin("not", vec![bool()], bool(), Kind::Fixed);
+        self.add_builtin("and", vec![bool()], bool(), Kind::LeftAssoc);
+        self.add_builtin("or", vec![bool()], bool(), Kind::LeftAssoc);
+        self.add_builtin("xor", vec![bool()], bool(), Kind::LeftAssoc);
+        self.add_builtin("=>", vec![bool()], bool(), Kind::RightAssoc);
+        // sort-parametric
+        self.add_builtin("=", vec![Pattern::Var(0)], bool(), Kind::Chainable);
+        self.add_builtin("distinct", vec![Pattern::Var(0)], bool(), Kind::Pairwise);
+        self.add_builtin("ite", vec![bool(), Pattern::Var(0), Pattern::Var(0)], Pattern::Var(0), Kind::Fixed);
+        let lattice = |unit| Props { commutative: true, associative: true, idempotent: true, identity: Some(Symbol::BoolLit(unit)) };
+        self.set_props("and", lattice(true));
+        self.set_props("or", lattice(false));
+        self.set_props("xor", Props { commutative: true, associative: true, idempotent: false, identity: Some(Symbol::BoolLit(false)) });
+        for op in ["=", "distinct"] {
+            self.set_props(op, Props { commutative: true, ..Props::default() });
         }
-        self.symbol_tbl.insert("not".to_owned(), (vec![("a".to_owned(), Sort::Bool)], Sort::Bool));
-        self.symbol_tbl.insert("=>".to_owned(), (vec![("a".to_owned(), Sort::Bool), ("b".to_owned(), Sort::Bool)], Sort::Bool));
-        self.symbol_tbl.insert("ite".to_owned(), (vec![("a".to_owned(), Sort::Bool), ("b".to_owned(), Sort::Bool), ("c".to_owned(), Sort::Bool)], Sort::Bool));
     }
 
     fn add_integers(&mut self) {
-        // only support upto 5-ary
-        for op in vec! ["="] {
-            for names in vec! [vec! ["a", "b"], vec! ["a", "b"], vec! ["a", "b", "c"], vec! ["a", "b", "c", "d"], vec! ["a", "b", "c", "d", "e"]] {
-                let params = names.into_iter().map(|n| (n.to_owned(), Sort::Int)).collect();
-                self.symbol_tbl.insert(op.to_owned(), (params, Sort::Bool));
+        let int = || Pattern::Is(Sort::Int);
+        for op in ["<", "<=", ">", ">="] {
+            self.add_builtin(op, vec![int()], Pattern::Is(Sort::Bool), Kind::Chainable);
+        }
+        // (- x) is negation
+        for op in ["+", "*", "-"] {
+            self.add_builtin(op, vec![int()], int(), Kind::LeftAssoc);
+        }
+        let monoid = |unit| Props { commutative: true, associative: true, idempotent: false, identity: Some(Symbol::IntLit(unit)) };
+        self.set_props("+", monoid(0));
+        self.set_props("*", monoid(1));
+    }
+
+    fn add_strings(&mut self) {
+        let string = || Pattern::Is(Sort::String);
+        let int = || Pattern::Is(Sort::Int);
+        let bool = || Pattern::Is(Sort::Bool);
+        self.add_builtin("str.++", vec![string()], string(), Kind::LeftAssoc);
+        self.set_props("str.++", Props { associative: true, identity: Some(Symbol::StrLit(String::new())), ..Props::default() });
+        self.add_builtin("str.len", vec![string()], int(), Kind::Fixed);
+        self.add_builtin("str.substr", vec![string(), int(), int()], string(), Kind::Fixed);
+        self.add_builtin("str.at", vec![string(), int()], string(), Kind::Fixed);
+        self.add_builtin("str.indexof", vec![string(), string(), int()], int(), Kind::Fixed);
+        self.add_builtin("str.replace", vec![string(), string(), string()], string(), Kind::Fixed);
+        self.add_builtin("str.to_int", vec![string()], int(), Kind::Fixed);
+        self.add_builtin("str.from_int", vec![int()], string(), Kind::Fixed);
+        for op in ["str.prefixof", "str.suffixof", "str.contains"] {
+            self.add_builtin(op, vec![string(), string()], bool(), Kind::Fixed);
+        }
+    }
+
+    fn add_arrays(&mut self) {
+        // arrays are parametric in both their index (0) and element (1) sorts
+        let array = || Pattern::Array(Box::new(Pattern::Var(0)), Box::new(Pattern::Var(1)));
+        self.add_builtin("select", vec![array(), Pattern::Var(0)], Pattern::Var(1), Kind::Fixed);
+        self.add_builtin("store", vec![array(), Pattern::Var(0), Pattern::Var(1)], array(), Kind::Fixed);
+    }
+}
+
+// how the arguments of an interpreted symbol line up with its parameters
+#[derive(PartialEq, Clone, Copy)]
+pub enum Kind {
+    // exactly the listed parameters
+    Fixed,
+    // one or more copies of the only parameter: (f a b c) is (f (f a b) c)
+    LeftAssoc,
+    // two or more copies of the only parameter: (f a b c) is (f a (f b c))
+    RightAssoc,
+    // two or more copies of the only parameter: (f a b c) is (and (f a b) (f b c))
+    Chainable,
+    // two or more copies of the only parameter: (f a b c) is (and (f a b) (f a c) (f b c))
+    Pairwise,
+}
+
+// a sort that may mention sort variables
+#[derive(Clone)]
+pub enum Pattern {
+    Is(Sort),
+    Var(usize),
+    Array(Box<Pattern>, Box<Pattern>),
+}
+
+impl Pattern {
+    fn unify(&self, s: &Sort, binding: &mut Vec<Option<Sort>>) -> bool {
+        match (self, s) {
+            (Pattern::Is(p), s) => p == s,
+            (Pattern::Var(v), s) => {
+                if binding.len() <= *v {
+                    binding.resize(v + 1, None);
+                }
+                match &binding[*v] {
+                    Some(b) => b == s,
+                    None => {
+                        binding[*v] = Some(s.clone());
+                        true
+                    }
+                }
             }
+            (Pattern::Array(i, e), Sort::Array(si, se)) => i.unify(si, binding) && e.unify(se, binding),
+            _ => false
         }
-        // binary
-        for op in vec! ["<", "<=", ">", ">="] {
-            for names in vec! [vec! ["a", "b"]] {
-                let params = names.into_iter().map(|n| (n.to_owned(), Sort::Int)).collect();
-                self.symbol_tbl.insert(op.to_owned(), (params, Sort::Bool));
+    }
+
+    fn instantiate(&self, binding: &[Option<Sort>]) -> Option<Sort> {
+        match self {
+            Pattern::Is(s) => Some(s.clone()),
+            Pattern::Var(v) => binding.get(*v)?.clone(),
+            Pattern::Array(i, e) => Some(Sort::Array(Box::new(i.instantiate(binding)?), Box::new(e.instantiate(binding)?))),
+        }
+    }
+
+    fn vars(&self, vs: &mut Vec<usize>) {
+        match self {
+            Pattern::Is(_) => (),
+            Pattern::Var(v) => if !vs.contains(v) {
+                vs.push(*v)
+            },
+            Pattern::Array(i, e) => {
+                i.vars(vs);
+                e.vars(vs);
             }
         }
-        // only support upto 5-ary
-        for op in vec! ["+", "*", "-"] {
-            for names in vec! [vec! ["a", "b"], vec! ["a", "b"], vec! ["a", "b", "c"], vec! ["a", "b", "c", "d"], vec! ["a", "b", "c", "d", "e"]] {
-                let params = names.into_iter().map(|n| (n.to_owned(), Sort::Int)).collect();
-                self.symbol_tbl.insert(op.to_owned(), (params, Sort::Int));
+    }
+}
+
+// the algebraic laws an interpreted symbol obeys, so the search can keep one term of each family they relate
+#[derive(Clone, Default)]
+pub struct Props {
+    // (f a b) is (f b a)
+    pub commutative: bool,
+    // (f (f a b) c) is (f a (f b c))
+    pub associative: bool,
+    // (f a a) is a
+    pub idempotent: bool,
+    // (f e a) and (f a e) are a
+    pub identity: Option<Symbol>,
+}
+
+// the signature of an interpreted symbol
+#[derive(Clone)]
+pub struct Builtin {
+    pub params: Vec<Pattern>,
+    pub rsort: Pattern,
+    pub kind: Kind,
+    pub props: Props,
+}
+
+impl Builtin {
+    // the parameter patterns for a given number of arguments
+    fn expand(&self, arity: usize) -> Option<Vec<&Pattern>> {
+        let min = match self.kind {
+            Kind::Fixed => return if arity == self.params.len() {Some(self.params.iter().collect())} else {None},
+            Kind::LeftAssoc => 1,
+            _ => 2,
+        };
+        if arity >= min {
+            Some(vec![&self.params[0]; arity])
+        } else {
+            None
+        }
+    }
+
+    // the result sort of applying this symbol to arguments of the given sorts
+    pub fn apply(&self, args: &[Sort]) -> Option<Sort> {
+        let params = self.expand(args.len())?;
+        let mut binding = vec![];
+        for (p, a) in params.iter().zip(args.iter()) {
+            if !p.unify(a, &mut binding) {
+                return None
             }
         }
-        self.symbol_tbl.insert("-".to_owned(), (vec![("a".to_owned(), Sort::Int)], Sort::Int));
-        self.symbol_tbl.insert("ite".to_owned(), (vec![("a".to_owned(), Sort::Bool), ("b".to_owned(), Sort::Int), ("c".to_owned(), Sort::Int)], Sort::Int));
+        self.rsort.instantiate(&binding)
+    }
+
+    // all the ways of calling this symbol, with the fewest arguments, to get a result of sort rsort
+    // using only arguments of the given sorts
+    pub fn instances(&self, rsort: &Sort, sorts: &[Sort]) -> Vec<Vec<Sort>> {
+        let mut binding = vec![];
+        if !self.rsort.unify(rsort, &mut binding) {
+            return vec![]
+        }
+        let params = self.expand(if self.kind == Kind::Fixed {self.params.len()} else {2}).expect("unreachable");
+        let mut free = vec![];
+        for p in params.iter() {
+            p.vars(&mut free);
+        }
+        free.retain(|v| binding.get(*v).is_none_or(|b| b.is_none()));
+
+        // try every assignment of the given sorts to the unconstrained variables
+        let mut bindings = vec![binding];
+        for v in free {
+            bindings = bindings.into_iter().flat_map(|b| sorts.iter().map(move |s| {
+                let mut b = b.clone();
+                if b.len() <= v {
+                    b.resize(v + 1, None);
+                }
+                b[v] = Some(s.clone());
+                b
+            })).collect();
+        }
+        bindings.into_iter()
+            .filter_map(|b| params.iter().map(|p| p.instantiate(&b)).collect::<Option<Vec<Sort>>>())
+            .filter(|ps| ps.iter().all(|p| sorts.contains(p)))
+            .collect()
     }
 }
 
-#[derive(PartialEq, Eq, Copy, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Sort {
     Bool,
     Int,
+    String,
+    Array(Box<Sort>, Box<Sort>),
+    Datatype(String),
 }
 
 impl Sort {
     pub fn new(s: &str) -> Sort {
-        match s {
-            "Bool" => Sort::Bool,
-            "Int" => Sort::Int,
-            _ => panic!(format!("sort {} not supported", s))
+        Sort::parse(s).unwrap_or_else(|| panic!("sort {} not supported", s))
+    }
+
+    pub fn parse(s: &str) -> Option<Sort> {
+        let tokens: Vec<String> = s.replace("(", " ( ").replace(")", " ) ")
+            .split_whitespace()
+            .map(|t| t.to_owned())
+            .collect();
+        let mut tokens = tokens.iter();
+        let sort = Sort::from_tokens(&mut tokens)?;
+        match tokens.next() {
+            None => Some(sort),
+            Some(_) => None
         }
     }
+
+    fn from_tokens(tokens: &mut std::slice::Iter<String>) -> Option<Sort> {
+        match tokens.next()?.as_str() {
+            "Bool" => Some(Sort::Bool),
+            "Int" => Some(Sort::Int),
+            "String" => Some(Sort::String),
+            ")" => None,
+            "(" => {
+                let sort = match tokens.next()?.as_str() {
+                    "Array" => {
+                        let index = Sort::from_tokens(tokens)?;
+                        let element = Sort::from_tokens(tokens)?;
+                        Sort::Array(Box::new(index), Box::new(element))
+                    }
+                    _ => return None
+                };
+                match tokens.next()?.as_str() {
+                    ")" => Some(sort),
+                    _ => None
+                }
+            }
+            // anything else must be the name of a datatype
+            name => Some(Sort::Datatype(name.to_owned()))
+        }
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, Sort::Array(_, _))
+    }
 }
 
 impl fmt::Display for Sort {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let printable = match *self {
-            Sort::Bool => "Bool",
-            Sort::Int => "Int",
-        };
-        write!(f, "{}", printable)
+        match self {
+            Sort::Bool => write!(f, "Bool"),
+            Sort::Int => write!(f, "Int"),
+            Sort::String => write!(f, "String"),
+            Sort::Array(i, e) => write!(f, "(Array {} {})", i, e),
+            Sort::Datatype(n) => write!(f, "{}", n),
+        }
     }
 }
 
-
+#[derive(Clone)]
 pub struct Logic {
     pub q: bool,
+    pub a: bool,
+    pub dt: bool,
+    pub s: bool,
     pub lia: bool,
     pub uf: bool,
 }
@@ -236,43 +588,114 @@ impl Logic {
     pub fn new() -> Logic {
         let l = Logic {
             q: false,
+            a: false,
+            dt: false,
+            s: false,
             lia: false,
             uf: false,
         };
         l
     }
     pub fn to_logic(s: &str) -> Logic {
-        match s {
+        Logic::parse(s).unwrap_or_else(|| panic!("logic {} not supported", s))
+    }
+
+    pub fn parse(s: &str) -> Option<Logic> {
+        let logic = match s {
             "QF_UF" => Logic {
                 q: false,
+                a: false,
+                dt: false,
+                s: false,
                 lia: false,
                 uf: true,
             },
             "QF_LIA" => Logic {
                 q: false,
+                a: false,
+                dt: false,
+                s: false,
                 lia: true,
                 uf: false,
             },
             "QF_UFLIA" => Logic {
                 q: false,
+                a: false,
+                dt: false,
+                s: false,
                 lia: true,
                 uf: true,
             },
+            "UFLIA" => Logic {
+                q: true,
+                a: false,
+                dt: false,
+                s: false,
+                lia: true,
+                uf: true,
+            },
+            "QF_ALIA" => Logic {
+                q: false,
+                a: true,
+                dt: false,
+                s: false,
+                lia: true,
+                uf: false,
+            },
+            "QF_AUFLIA" => Logic {
+                q: false,
+                a: true,
+                dt: false,
+                s: false,
+                lia: true,
+                uf: true,
+            },
+            "QF_DT" => Logic {
+                q: false,
+                a: false,
+                dt: true,
+                s: false,
+                lia: false,
+                uf: false,
+            },
+            "QF_UFDTLIA" => Logic {
+                q: false,
+                a: false,
+                dt: true,
+                s: false,
+                lia: true,
+                uf: true,
+            },
+            "QF_SLIA" => Logic {
+                q: false,
+                a: false,
+                dt: false,
+                s: true,
+                lia: true,
+                uf: false,
+            },
             "ALL" => Logic {
                 q: true,
+                a: true,
+                dt: true,
+                s: true,
                 lia: true,
                 uf: true,
             },
-            _ => panic!(format!("logic {} not supported", s))
-        }
+            _ => return None
+        };
+        Some(logic)
     }
 }
 
 impl fmt::Display for Logic {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let q = if self.q {""} else {"QF_"};
+        let a = if self.a {"A"} else {""};
         let uf = if self.uf {"UF"} else {""};
+        let dt = if self.dt {"DT"} else {""};
+        let s = if self.s {"S"} else {""};
         let lia = if self.lia {"LIA"} else {""};
-        write!(f, "{}{}{}", q, uf, lia)
+        write!(f, "{}{}{}{}{}{}", q, a, uf, dt, s, lia)
     }
 }
\ No newline at end of file
@@ -1,6 +1,12 @@
 use crate::ast::{Term, Symbol};
-use crate::ctx::{Context, Solution, Sort};
+use crate::ctx::{Context, Kind, Solution, Sort};
 use crate::qry::{Query, Command};
+use crate::rwr::substitute;
+
+// how deeply calls to defined functions can nest, and how many there can be in all, before we give
+// up on a value: recursive definitions need not bottom out, or may take too long to
+const MAX_DEPTH: usize = 64;
+const MAX_FUEL: usize = 4096;
 
 impl Query {
     pub fn eval(&self, s: &Solution) -> Option<bool> {
@@ -22,154 +28,40 @@ impl Query {
 }
 
 impl Context {
+    // the value of the term, or a non-terminal if that depends on one or on calls nested too deep
     pub fn eval(&self, s: &Solution, t: &Term) -> Symbol {
+        let mut fuel = MAX_FUEL;
+        self.eval_bounded(s, t, 0, &mut fuel)
+    }
+
+    fn eval_bounded(&self, s: &Solution, t: &Term, depth: usize, fuel: &mut usize) -> Symbol {
         let mut args = t.get_args();
         match t.get_symbol() {
             Symbol::IntLit(i) => Symbol::IntLit(*i),
+            Symbol::StrLit(l) => Symbol::StrLit(l.clone()),
             Symbol::BoolLit(b) => Symbol::BoolLit(*b),
-            Symbol::NonTerm(s, n) => Symbol::NonTerm(*s, n.clone()),
+            Symbol::ArrayLit(_, _, _)
+            | Symbol::DtLit(_, _, _) => t.get_symbol().clone(),
+            Symbol::ConstArray(asort) => match self.eval_bounded(s, args.next().expect("must have default element"), depth, fuel) {
+                Symbol::NonTerm(_, n) => Symbol::NonTerm(asort.clone(), n),
+                Symbol::Func(n) => panic!("{} not evaluated!", n),
+                d => Symbol::mk_array(asort.clone(), d, vec![])
+            },
+            Symbol::NonTerm(s, n) => Symbol::NonTerm(s.clone(), n.clone()),
             Symbol::Func(name) => {
                 match name.as_str() {
-                    "+" => {
-                        let mut result = 0;
-                        for a in args {
-                            match self.eval(s, a) {
-                                Symbol::IntLit(b) => result = result + b,
-                                Symbol::NonTerm(Sort::Int, n) => return Symbol::NonTerm(Sort::Int, n),
-                                other => panic!("expecting Int, got {}", other)
-                            }
-                        }
-                        Symbol::IntLit(result)
-                    },
-                    "-" => {
-                        let mut result = 0;
-                        for a in args {
-                            match self.eval(s, a) {
-                                Symbol::IntLit(b) => result = result - b,
-                                Symbol::NonTerm(Sort::Int, n) => return Symbol::NonTerm(Sort::Int, n),
-                                other => panic!("expecting Int, got {}", other)
-                            }
-                        }
-                        Symbol::IntLit(result)
-                    },
-                    "*" => {
-                        let mut result = 0;
-                        for a in args {
-                            match self.eval(s, a) {
-                                Symbol::IntLit(b) => result = result * b,
-                                Symbol::NonTerm(Sort::Int, n) => return Symbol::NonTerm(Sort::Int, n),
-                                other => panic!("expecting Int, got {}", other)
-                            }
-                        }
-                        Symbol::IntLit(result)
-                    },
-                    "not" => match self.eval(s, args.next().expect("must have first argument")) {
-                        Symbol::BoolLit(b) => Symbol::BoolLit(!b),
-                        Symbol::NonTerm(Sort::Bool, n) => Symbol::NonTerm(Sort::Bool, n),
-                        other => panic!("expecting bool, got {}", other)
-                    },
-                    "or" => {
-                        let mut result = false;
-                        for a in args {
-                            match self.eval(s, a) {
-                                Symbol::BoolLit(b) => result = result || b,
-                                Symbol::NonTerm(Sort::Bool, n) => return Symbol::NonTerm(Sort::Bool, n),
-                                other => panic!("expecting bool, got {}", other)
-                            }
-                        }
-                        Symbol::BoolLit(result)
-                    },
-                    "and" => {
-                        let mut result = true;
-                        for a in args {
-                            match self.eval(s, a) {
-                                Symbol::BoolLit(b) => result = result && b,
-                                Symbol::NonTerm(Sort::Bool, n) => return Symbol::NonTerm(Sort::Bool, n),
-                                other => panic!("expecting bool, got {}", other)
-                            }
-                        }
-                        Symbol::BoolLit(result)
-                    },
-                    "=>" => {
-                        match self.eval(s, args.next().expect("must have first argument")) {
-                            Symbol::BoolLit(b1) => {
-                                match self.eval(s, args.next().expect("must have second argument")) {
-                                    Symbol::BoolLit(b2) => Symbol::BoolLit(!b1 || b2,),
-                                    Symbol::NonTerm(Sort::Bool, n) => Symbol::NonTerm(Sort::Bool, n),
-                                    other => panic!("expecting bool, got {}", other)
-                                }
-                            }
-                            Symbol::NonTerm(Sort::Bool, n) => Symbol::NonTerm(Sort::Bool, n),
-                            other => panic!("expecting bool, got {}", other)
-                        }
-                    },
-                    ">" => {
-                        match self.eval(s, args.next().expect("must have first argument")) {
-                            Symbol::IntLit(i1) => {
-                                let l = args.next().expect("must have second argument");
-                                debug!("here: {}", l);
-                                match self.eval(s, l){
-                                    Symbol::IntLit(i2) => Symbol::BoolLit(i1 > i2,),
-                                    Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                                    other => panic!("expecting int, got {}", other)
-                                }
-                            }
-                            Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                            other => panic!("expecting int, got {}", other)
-                        }
-                    },
-                    "<" => {
-                        match self.eval(s, args.next().expect("must have first argument")) {
-                            Symbol::IntLit(i1) => {
-                                match self.eval(s, args.next().expect("must have second argument")) {
-                                    Symbol::IntLit(i2) => Symbol::BoolLit(i1 < i2,),
-                                    Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                                    other => panic!("expecting int, got {}", other)
-                                }
-                            }
-                            Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                            other => panic!("expecting int, got {}", other)
-                        }
-                    },
-                    ">=" => {
-                        match self.eval(s, args.next().expect("must have first argument")) {
-                            Symbol::IntLit(i1) => {
-                                match self.eval(s, args.next().expect("must have second argument")) {
-                                    Symbol::IntLit(i2) => Symbol::BoolLit(i1 >= i2,),
-                                    Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                                    other => panic!("expecting int, got {}", other)
-                                }
-                            }
-                            Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                            other => panic!("expecting int, got {}", other)
-                        }
-                    },
-                    "<=" => {
-                        match self.eval(s, args.next().expect("must have first argument")) {
-                            Symbol::IntLit(i1) => {
-                                match self.eval(s, args.next().expect("must have second argument")) {
-                                    Symbol::IntLit(i2) => Symbol::BoolLit(i1 <= i2,),
-                                    Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                                    other => panic!("expecting int, got {}", other)
-                                }
-                            }
-                            Symbol::NonTerm(Sort::Int, n) => Symbol::NonTerm(Sort::Bool, n),
-                            other => panic!("expecting int, got {}", other)
-                        }
-                    },
-                    // polymorphic
                     "ite" => {
-                        match self.eval(s, args.next().expect("must have first argument")) {
+                        match self.eval_bounded(s, args.next().expect("must have first argument"), depth, fuel) {
                             Symbol::BoolLit(b1) => {
                                 if b1 {
-                                    match self.eval(s, args.next().expect("must have second argument")) {
+                                    match self.eval_bounded(s, args.next().expect("must have second argument"), depth, fuel) {
                                         Symbol::Func(n) => panic!("{} not evaluated!", n),
                                         sym => sym 
                                     }
                                 }
                                 else {
                                     args.next();
-                                    match self.eval(s, args.next().expect("must have third argument")) {
+                                    match self.eval_bounded(s, args.next().expect("must have third argument"), depth, fuel) {
                                         Symbol::Func(n) => panic!("{} not evaluated!", n),
                                         sym => sym 
                                     }
@@ -182,40 +74,107 @@ impl Context {
                             other => panic!("expecting bool, got {}", other)
                         }
                     },
-                    "=" => {
-                        let eval_args: Vec<Symbol> = args.into_iter().map(|a| self.eval(s, a)).collect();
-
-                        for i in 1..eval_args.len() {
-                            if eval_args[0] != eval_args[i] {
-                                return Symbol::BoolLit(false)
+                    _ if self.get_builtin(name).is_some() => {
+                        let kind = self.get_builtin(name).expect("unreachable").kind;
+                        // left to right, and only as far as needed, so that guards keep recursion in check
+                        let mut vals = vec![];
+                        while let Some(a) = args.next() {
+                            let v = self.eval_bounded(s, a, depth, fuel);
+                            if let Some(b) = short_circuit(name, &v, args.len() == 0) {
+                                return Symbol::BoolLit(b)
                             }
-                        };
-
-                        Symbol::BoolLit(true)
-                    }
+                            vals.push(v);
+                        }
+                        for v in vals.iter() {
+                            if let Symbol::NonTerm(_, n) = v {
+                                // unless the arguments we know are enough
+                                if let Some(b) = decided(name, &vals) {
+                                    return Symbol::BoolLit(b)
+                                }
+                                return Symbol::NonTerm(self.get_sort(t).expect("must have sort"), n.clone())
+                            }
+                        }
+                        match kind {
+                            Kind::Fixed => eval_fixed(name, vals),
+                            Kind::LeftAssoc => {
+                                let mut vals = vals.into_iter();
+                                let first = vals.next().expect("must have first argument");
+                                if vals.len() == 0 {
+                                    eval_unary(name, first)
+                                } else {
+                                    vals.fold(first, |acc, v| eval_binary(name, acc, v))
+                                }
+                            },
+                            Kind::RightAssoc => {
+                                let mut vals = vals.into_iter().rev();
+                                let last = vals.next().expect("must have last argument");
+                                vals.fold(last, |acc, v| eval_binary(name, v, acc))
+                            },
+                            Kind::Chainable => Symbol::BoolLit(vals.windows(2).all(|w| {
+                                eval_binary(name, w[0].clone(), w[1].clone()) == Symbol::BoolLit(true)
+                            })),
+                            Kind::Pairwise => Symbol::BoolLit((0..vals.len()).all(|i| {
+                                (i + 1..vals.len()).all(|j| vals[i] != vals[j])
+                            })),
+                        }
+                    },
+                    _ if self.get_constructor(name).is_some() => {
+                        let (dsort, _) = self.get_constructor(name).expect("unreachable");
+                        let mut fields = vec![];
+                        for a in args {
+                            match self.eval_bounded(s, a, depth, fuel) {
+                                Symbol::NonTerm(_, n) => return Symbol::NonTerm(dsort, n),
+                                v => fields.push(v)
+                            }
+                        }
+                        Symbol::DtLit(dsort, name.clone(), fields)
+                    },
+                    _ if self.get_selector(name).is_some() => {
+                        let (cname, idx, fsort) = self.get_selector(name).expect("unreachable");
+                        match self.eval_bounded(s, args.next().expect("must have first argument"), depth, fuel) {
+                            Symbol::DtLit(_, c, mut fields) => if &c == cname {
+                                fields.swap_remove(idx)
+                            } else {
+                                // SMT-LIB leaves selectors applied to the wrong constructor unspecified
+                                Symbol::NonTerm(fsort.clone(), "unspecified".to_owned())
+                            },
+                            Symbol::NonTerm(_, n) => Symbol::NonTerm(fsort.clone(), n),
+                            other => panic!("expecting datatype, got {}", other)
+                        }
+                    },
+                    _ if self.get_tester(name).is_some() => {
+                        let cname = self.get_tester(name).expect("unreachable");
+                        match self.eval_bounded(s, args.next().expect("must have first argument"), depth, fuel) {
+                            Symbol::DtLit(_, c, _) => Symbol::BoolLit(&c == cname),
+                            Symbol::NonTerm(_, n) => Symbol::NonTerm(Sort::Bool, n),
+                            other => panic!("expecting datatype, got {}", other)
+                        }
+                    },
                     _ => {
                         // we have a declared thing
                         assert!(self.get_decl(name).is_some(), "can't find {}", name);
         
-                        let entries = self.get_decl(name).expect("unreachable");
-                        assert!(entries.len() == 1, "too many candidates for {}", name);
-                        let (params, _) = entries.first().expect("unreachable");
-
-                        // create a temporary context for evaluating the body
-                        let mut tmp_sol = Context::new();
-                        tmp_sol.update_logic(self.get_logic());
-
-                        for (label, lsort) in params {
-                            let a = self.eval(s, args.next().expect("more params than arguments"));
-                            tmp_sol.add_decl(label.as_str(), vec![], lsort.clone());
-                            tmp_sol.add_body(label.as_str(), Term::mk_const(a));
+                        let (params, rsort) = self.get_decl(name).expect("unreachable");
+                        if depth >= MAX_DEPTH {
+                            return Symbol::NonTerm(rsort.clone(), "too-deep".to_owned())
+                        }
+                        if *fuel == 0 {
+                            return Symbol::NonTerm(rsort.clone(), "out-of-fuel".to_owned())
+                        }
+                        *fuel -= 1;
+
+                        // the values of the arguments stand in for the parameters
+                        let mut values = Solution::new();
+                        for (label, _) in params {
+                            let a = self.eval_bounded(s, args.next().expect("more params than arguments"), depth, fuel);
+                            values.insert(label.clone(), Term::mk_const(a));
                         }
                         
                         // find the body: it is either in the definitions or the solution
                         let body = self.get_body(name).unwrap_or_else(|| s.get(name).expect(format!("can't find body of {}", name).as_str()));
         
-                        // evaluate the body
-                        tmp_sol.eval(&Solution::new(), body)
+                        // evaluate the body, which may call this or other functions in turn
+                        self.eval_bounded(s, &substitute(&values, body), depth + 1, fuel)
         
                     }
                 }
@@ -224,9 +183,146 @@ impl Context {
     }
 }
 
+// a builtin applied to one value: negation, and the others are the identity on one argument
+fn eval_unary(name: &str, a: Symbol) -> Symbol {
+    match (name, a) {
+        ("-", Symbol::IntLit(i)) => Symbol::IntLit(-i),
+        (_, a) => a
+    }
+}
+
+// a builtin applied to two values, which variadic ones are folded over
+fn eval_binary(name: &str, a: Symbol, b: Symbol) -> Symbol {
+    match (name, a, b) {
+        ("=", a, b) => Symbol::BoolLit(a == b),
+        ("+", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::IntLit(i + j),
+        ("-", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::IntLit(i - j),
+        ("*", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::IntLit(i * j),
+        ("<", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::BoolLit(i < j),
+        ("<=", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::BoolLit(i <= j),
+        (">", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::BoolLit(i > j),
+        (">=", Symbol::IntLit(i), Symbol::IntLit(j)) => Symbol::BoolLit(i >= j),
+        ("and", Symbol::BoolLit(p), Symbol::BoolLit(q)) => Symbol::BoolLit(p && q),
+        ("or", Symbol::BoolLit(p), Symbol::BoolLit(q)) => Symbol::BoolLit(p || q),
+        ("xor", Symbol::BoolLit(p), Symbol::BoolLit(q)) => Symbol::BoolLit(p != q),
+        ("=>", Symbol::BoolLit(p), Symbol::BoolLit(q)) => Symbol::BoolLit(!p || q),
+        ("str.++", Symbol::StrLit(x), Symbol::StrLit(y)) => Symbol::StrLit(x + &y),
+        (name, a, b) => panic!("can't apply {} to {} and {}", name, a, b)
+    }
+}
+
+fn eval_fixed(name: &str, args: Vec<Symbol>) -> Symbol {
+    let mut args = args.into_iter();
+    match name {
+        "not" => match args.next().expect("must have first argument") {
+            Symbol::BoolLit(b) => Symbol::BoolLit(!b),
+            other => panic!("expecting bool, got {}", other)
+        },
+        "select" => match args.next().expect("must have first argument") {
+            Symbol::ArrayLit(_, d, entries) => {
+                let i = args.next().expect("must have second argument");
+                match entries.into_iter().find(|(j, _)| j == &i) {
+                    Some((_, e)) => e,
+                    None => *d
+                }
+            }
+            other => panic!("expecting array, got {}", other)
+        },
+        "store" => match args.next().expect("must have first argument") {
+            Symbol::ArrayLit(asort, d, mut entries) => {
+                let i = args.next().expect("must have second argument");
+                let e = args.next().expect("must have third argument");
+                entries.push((i, e));
+                Symbol::mk_array(asort, *d, entries)
+            }
+            other => panic!("expecting array, got {}", other)
+        },
+        _ => eval_string(name, &args.collect::<Vec<Symbol>>())
+    }
+}
+
+// the SMT-LIB semantics of string operators on fully evaluated arguments
+fn eval_string(name: &str, args: &[Symbol]) -> Symbol {
+    fn chars(s: &Symbol) -> Vec<char> {
+        match s {
+            Symbol::StrLit(l) => l.chars().collect(),
+            other => panic!("expecting string, got {}", other)
+        }
+    }
+    fn substr(s: &[char], i: i64, n: i64) -> String {
+        let len = s.len() as i64;
+        if i < 0 || i >= len || n <= 0 {
+            return String::new()
+        }
+        s[i as usize..std::cmp::min(i.saturating_add(n), len) as usize].iter().collect()
+    }
+    fn find(s: &[char], t: &[char], from: usize) -> Option<usize> {
+        (from..s.len() + 1).find(|j| s[*j..].starts_with(t))
+    }
+    match (name, args) {
+        ("str.len", [x]) => Symbol::IntLit(chars(x).len() as i64),
+        ("str.substr", [x, Symbol::IntLit(i), Symbol::IntLit(n)]) => Symbol::StrLit(substr(&chars(x), *i, *n)),
+        ("str.at", [x, Symbol::IntLit(i)]) => Symbol::StrLit(substr(&chars(x), *i, 1)),
+        ("str.indexof", [x, t, Symbol::IntLit(i)]) => {
+            let (x, t) = (chars(x), chars(t));
+            if *i < 0 || *i > x.len() as i64 {
+                return Symbol::IntLit(-1)
+            }
+            Symbol::IntLit(find(&x, &t, *i as usize).map_or(-1, |j| j as i64))
+        },
+        ("str.replace", [x, t, u]) => {
+            let (x, t, u) = (chars(x), chars(t), chars(u));
+            // only the first occurrence is replaced
+            let replaced: Vec<char> = match find(&x, &t, 0) {
+                Some(j) => x[..j].iter().chain(u.iter()).chain(x[j + t.len()..].iter()).cloned().collect(),
+                None => x
+            };
+            Symbol::StrLit(replaced.into_iter().collect())
+        },
+        ("str.to_int", [x]) => {
+            let x = chars(x);
+            if x.is_empty() || !x.iter().all(|c| c.is_ascii_digit()) {
+                return Symbol::IntLit(-1)
+            }
+            // Int is unbounded but our literals are not, so a value too big for them is unknown
+            match x.into_iter().collect::<String>().parse() {
+                Ok(n) => Symbol::IntLit(n),
+                Err(_) => Symbol::NonTerm(Sort::Int, "overflow".to_owned())
+            }
+        },
+        ("str.from_int", [Symbol::IntLit(n)]) => Symbol::StrLit(if *n < 0 {String::new()} else {n.to_string()}),
+        ("str.prefixof", [x, t]) => Symbol::BoolLit(chars(t).starts_with(&chars(x))),
+        ("str.suffixof", [x, t]) => Symbol::BoolLit(chars(t).ends_with(&chars(x))),
+        ("str.contains", [x, t]) => Symbol::BoolLit(find(&chars(x), &chars(t), 0).is_some()),
+        _ => panic!("cannot apply {} to {:?}", name, args)
+    }
+}
+
+// the value of a connective once it has an argument that decides it, whatever the rest are
+fn short_circuit(name: &str, v: &Symbol, last: bool) -> Option<bool> {
+    match (name, v) {
+        ("and", Symbol::BoolLit(false)) => Some(false),
+        ("or", Symbol::BoolLit(true)) => Some(true),
+        ("=>", Symbol::BoolLit(false)) if !last => Some(true),
+        _ => None
+    }
+}
+
+// the value of a connective whatever its unknown arguments turn out to be, if it has one
+fn decided(name: &str, vals: &[Symbol]) -> Option<bool> {
+    let is = |v: &Symbol, b: bool| v == &Symbol::BoolLit(b);
+    match name {
+        "and" if vals.iter().any(|v| is(v, false)) => Some(false),
+        "or" if vals.iter().any(|v| is(v, true)) => Some(true),
+        // (=> a b c) is (or (not a) (not b) c)
+        "=>" if vals[..vals.len() - 1].iter().any(|v| is(v, false)) || is(&vals[vals.len() - 1], true) => Some(true),
+        _ => None
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     use crate::qry::Query;
     use crate::ctx::{Solution};
@@ -255,14 +351,127 @@ mod test {
         assert!(query.eval(&s).unwrap());
     }
 
+    #[test]
+    fn test_eval_array(){
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/qfauflia.smt2").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        let unparsed_answer = fs::read_to_string("tests/data/qfauflia_result.smt2").expect("cannot read file");
+        let s = query.parse_answer(&unparsed_answer).expect("cannot parse file");
+        assert!(query.eval(&s).unwrap());
+
+        let unparsed_answer = fs::read_to_string("tests/data/qfauflia_lambda_result.smt2").expect("cannot read file");
+        let s = query.parse_answer(&unparsed_answer).expect("cannot parse file");
+        assert!(query.eval(&s).unwrap());
+    }
+
+    #[test]
+    fn test_eval_array_ext() {
+        let mut q = Query::new();
+        q.set_logic("QF_ALIA");
+        q.declare_const("a", "(Array Int Int)");
+        let a = q.mk_const("a");
+        let one = q.mk_const("1");
+        let zero = q.mk_const("0");
+        let st = q.mk_app("store", vec! [Arc::clone(&a), Arc::clone(&one), Arc::clone(&zero)]);
+        q.assert(q.mk_app("=", vec! [st, a]));
+
+        let answer = "sat\n(model\n(define-fun a () (Array Int Int) ((as const (Array Int Int)) 0))\n)";
+        let sol = q.parse_answer(answer).expect("cannot parse answer");
+        // storing the default element does not change the array
+        assert!(q.eval(&sol).unwrap());
+    }
+
+    #[test]
+    fn test_eval_datatype(){
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/qfdt.smt2").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        let unparsed_answer = fs::read_to_string("tests/data/qfdt_result.smt2").expect("cannot read file");
+        let s = query.parse_answer(&unparsed_answer).expect("cannot parse file");
+        assert!(query.eval(&s).unwrap());
+    }
+
+    #[test]
+    fn test_eval_datatype_unspecified(){
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/qfdt.smt2").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        // (seq (body m)) is not determined when the body is not a ping
+        let s = query.parse_answer("sat\n(model\n(define-fun m () Msg (pong 3 done))\n)").expect("cannot parse answer");
+        assert!(query.eval(&s).is_none());
+    }
+
+    #[test]
+    fn test_eval_string(){
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/qfslia.smt2").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        let unparsed_answer = fs::read_to_string("tests/data/qfslia_result.smt2").expect("cannot read file");
+        let s = query.parse_answer(&unparsed_answer).expect("cannot parse file");
+        assert!(query.eval(&s).unwrap());
+    }
+
+    #[test]
+    fn test_eval_string_edge_cases() {
+        use super::eval_string;
+        use crate::ast::Symbol;
+        let s = |v: &str| Symbol::StrLit(v.to_owned());
+        let i = |v: i64| Symbol::IntLit(v);
+        assert!(eval_string("str.substr", &[s("hello"), i(3), i(10)]) == s("lo"));
+        assert!(eval_string("str.substr", &[s("hello"), i(-1), i(2)]) == s(""));
+        assert!(eval_string("str.at", &[s("hello"), i(5)]) == s(""));
+        assert!(eval_string("str.indexof", &[s("hello"), s("l"), i(3)]) == i(3));
+        assert!(eval_string("str.indexof", &[s("hello"), s(""), i(5)]) == i(5));
+        assert!(eval_string("str.indexof", &[s("hello"), s("z"), i(0)]) == i(-1));
+        assert!(eval_string("str.replace", &[s("hello"), s(""), s("!")]) == s("!hello"));
+        assert!(eval_string("str.to_int", &[s("042")]) == i(42));
+        assert!(eval_string("str.to_int", &[s("4a")]) == i(-1));
+        assert!(matches!(eval_string("str.to_int", &[s("99999999999999999999")]), Symbol::NonTerm(_, _)));
+        assert!(eval_string("str.substr", &[s("hello"), i(1), i(i64::MAX)]) == s("ello"));
+        assert!(eval_string("str.from_int", &[i(-3)]) == s(""));
+    }
+
+    #[test]
+    fn test_eval_variadic() {
+        use crate::ast::Symbol;
+        let mut q = Query::new();
+        q.set_logic("QF_LIA");
+        let n = |v: &str| q.mk_const(v);
+        let eval = |t| q.peek_ctx().eval(&Solution::new(), t);
+        let sub = q.mk_app("-", vec! [n("10"), n("3"), n("2")]);
+        assert!(eval(&sub) == Symbol::IntLit(5));
+        let neg = q.mk_app("-", vec! [n("4")]);
+        assert!(eval(&neg) == Symbol::IntLit(-4));
+        let mul = q.mk_app("*", vec! [n("2"), n("3"), n("4")]);
+        assert!(eval(&mul) == Symbol::IntLit(24));
+        let lt = q.mk_app("<", vec! [n("1"), n("2"), n("2")]);
+        assert!(eval(&lt) == Symbol::BoolLit(false));
+        let le = q.mk_app("<=", vec! [n("1"), n("2"), n("2")]);
+        assert!(eval(&le) == Symbol::BoolLit(true));
+        let distinct = q.mk_app("distinct", vec! [n("1"), n("2"), n("1")]);
+        assert!(eval(&distinct) == Symbol::BoolLit(false));
+        // => associates to the right
+        let imp = q.mk_app("=>", vec! [n("false"), n("true"), n("false")]);
+        assert!(eval(&imp) == Symbol::BoolLit(true));
+    }
+
     #[test]
     fn test_partial_false() {
         let mut q = Query::new();
         q.set_logic("QF_UFLIA");
         q.declare_fun("f", vec! ["Int", "Int"], "Bool");
         let one = q.mk_const("1");
-        let mone = q.mk_neg(Rc::clone(&one));
-        let a1 = q.mk_app("f", vec! [Rc::clone(&mone),  Rc::clone(&one)]);
+        let mone = q.mk_neg(Arc::clone(&one));
+        let a1 = q.mk_app("f", vec! [Arc::clone(&mone),  Arc::clone(&one)]);
         q.assert(a1);
         q.check_sat();
         q.get_model();
@@ -272,9 +481,9 @@ mod test {
         let t = q.mk_const("true");
         let seven = q.mk_const("7");
         let arg = q.mk_const("!a!");
-        let ge = q.mk_ge(Rc::clone(&arg), Rc::clone(&seven));
+        let ge = q.mk_ge(Arc::clone(&arg), Arc::clone(&seven));
         let nt = q.mk_nonterminal("N", "Bool");
-        let ite = q.mk_ite(Rc::clone(&t), Rc::clone(&ge), Rc::clone(&nt));
+        let ite = q.mk_ite(Arc::clone(&t), Arc::clone(&ge), Arc::clone(&nt));
 
         sol.insert("f".to_owned(), ite);
 
@@ -288,8 +497,8 @@ mod test {
         q.set_logic("QF_UFLIA");
         q.declare_fun("f", vec! ["Int", "Int"], "Bool");
         let one = q.mk_const("1");
-        let mone = q.mk_neg(Rc::clone(&one));
-        let a1 = q.mk_app("f", vec! [Rc::clone(&mone), Rc::clone(&one)]);
+        let mone = q.mk_neg(Arc::clone(&one));
+        let a1 = q.mk_app("f", vec! [Arc::clone(&mone), Arc::clone(&one)]);
         q.assert(a1);
         q.check_sat();
         q.get_model();
@@ -299,9 +508,9 @@ mod test {
         let cond = q.mk_const("false");
         let seven = q.mk_const("7");
         let arg = q.mk_const("!a!");
-        let ge = q.mk_ge(Rc::clone(&arg), Rc::clone(&seven));
+        let ge = q.mk_ge(Arc::clone(&arg), Arc::clone(&seven));
         let nt = q.mk_nonterminal("N", "Bool");
-        let ite = q.mk_ite(Rc::clone(&cond), Rc::clone(&ge), Rc::clone(&nt));
+        let ite = q.mk_ite(Arc::clone(&cond), Arc::clone(&ge), Arc::clone(&nt));
 
         sol.insert("f".to_owned(), ite);
 
@@ -319,4 +528,54 @@ mod test {
         let s = query.parse_answer(&unparsed_answer).expect("cannot parse file");
         assert!(query.eval(&s).unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_eval_rec(){
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/uflia_rec.smt2").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        let mut sol = Solution::new();
+        sol.insert("x".to_owned(), query.mk_const("4"));
+        assert_eq!(query.eval(&sol), Some(true));
+        sol.insert("x".to_owned(), query.mk_const("3"));
+        assert_eq!(query.eval(&sol), Some(false));
+        // too many calls to know
+        sol.insert("x".to_owned(), query.mk_const("100"));
+        assert_eq!(query.eval(&sol), None);
+    }
+
+    #[test]
+    fn test_eval_decided(){
+        let mut q = Query::new();
+        q.set_logic("QF_LIA");
+        q.declare_const("x", "Int");
+        let x = q.mk_const("x");
+        let nt = q.mk_nonterminal("N", "Bool");
+        let pos = q.mk_ge(Arc::clone(&x), q.mk_const("1"));
+        let mut sol = Solution::new();
+        sol.insert("x".to_owned(), q.mk_const("0"));
+
+        // a connective can be known even if some argument is not
+        let ctx = q.peek_ctx();
+        let and = q.mk_app("and", vec![Arc::clone(&nt), Arc::clone(&pos)]);
+        assert_eq!(ctx.eval(&sol, &and).to_string(), "false");
+        let imp = q.mk_app("=>", vec![Arc::clone(&pos), Arc::clone(&nt)]);
+        assert_eq!(ctx.eval(&sol, &imp).to_string(), "true");
+        let or = q.mk_app("or", vec![Arc::clone(&nt), Arc::clone(&pos)]);
+        assert_eq!(ctx.eval(&sol, &or).to_string(), "?N?");
+    }
+
+    #[test]
+    fn test_eval_guarded(){
+        let mut query = Query::new();
+        query.parse_query("(set-logic UFLIA)(define-fun-rec down ((n Int)) Bool (=> (> n 0) (down (- n 1))))").expect("cannot parse query");
+        let terms = query.parse_terms("(= (down 3) (down 3))").expect("cannot parse terms");
+
+        // the guard stops the recursion, so each side takes one call per step down
+        let mut fuel = 8;
+        assert_eq!(query.peek_ctx().eval_bounded(&Solution::new(), &terms[0], 0, &mut fuel).to_string(), "true");
+        assert_eq!(fuel, 0);
+    }
+}
@@ -4,21 +4,26 @@ extern crate pest;
 #[macro_use] 
 extern crate log;
 extern crate env_logger;
-extern crate multimap;
 extern crate clap;
 extern crate bit_vec;
 
 use std::fs;
-use std::io;
-use clap::App;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use clap::{App, SubCommand};
 
 mod ast;
 mod ctx;
 mod evl;
 mod qry;
+mod rpl;
 mod rwr;
 mod smt;
 mod syn;
+mod trc;
 
 fn main() {
     env_logger::init();
@@ -29,9 +34,96 @@ fn main() {
                           .about("SMT-LIB Function Synthesis Engine")
                           .args_from_usage(
                               "[input] 'Sets the input file to use, stdin otherwise'
-                              -v, --verbose 'Verbose'")
+                              -v, --verbose 'Verbose'
+                              --timeout [seconds] 'Gives up on each problem after this long'
+                              --solver-timeout [seconds] 'Kills any solver call that takes longer than this'
+                              --max-candidates [n] 'Gives up after enumerating this many candidates'
+                              --max-iterations [n] 'Gives up after this many calls to the solver'
+                              -j, --jobs [n] 'Checks up to this many candidates at once'
+                              -t, --threads [n] 'Enumerates candidates with this many threads'
+                              --strategy [name] 'Searches top-down (default) or bottom-up'
+                              --solver [name] 'Checks candidates with cvc4 (default) or z3'
+                              --portfolio 'Races every strategy and solver, reporting which won'
+                              --prune 'Leaves out candidates the solver proves equivalent to ones seen before, comparing them first at the inputs the spec calls the function at'
+                              --symbolic-constants 'Leaves integer constants in candidates for the solver to find'
+                              --recursive 'Lets the function call itself on smaller arguments'
+                              --format [name] 'Prints answers as smt (default) or json'
+                              --trace [file] 'Writes down every candidate, solver query and answer to this file'
+                              --replay [file] 'Answers solver queries from this trace instead of calling the solver'
+                              --cache 'Keeps solver answers on disk and reuses them across runs'
+                              --cache-dir [dir] 'Keeps the cache here, implies --cache'
+                              --clear-cache 'Empties the cache first'
+                              --counterexamples [file] 'Starts from the counterexamples (models) in this file'
+                              --hints [file] 'Tries the candidate bodies in this file before searching'
+                              --components [names] 'Builds candidates with these defined functions too (comma separated)'
+                              --all-components 'Builds candidates with every defined function too'
+                              --export-counterexamples [file] 'Writes the final counterexamples to this file'")
+                          .subcommand(SubCommand::with_name("verify")
+                              .about("Checks a solution from elsewhere against the spec")
+                              .args_from_usage(
+                                  "<spec> 'The problem, with the function to synthesize'
+                                  <solution> 'The define-fun of the function'"))
+                          .subcommand(SubCommand::with_name("repl")
+                              .about("Reads commands one at a time, solving at each (check-synth)"))
                           .get_matches();
 
+    let cache_dir = matches.value_of("cache-dir").map_or_else(smt::Cache::default_dir, PathBuf::from);
+    if matches.is_present("clear-cache") {
+        smt::Cache::new(cache_dir.clone()).clear();
+        if !matches.is_present("input") {
+            return
+        }
+    }
+
+    let verbose = matches.is_present("verbose");
+    let seconds = |flag| matches.value_of(flag).map(|v| Duration::from_secs_f64(v.parse().expect("must give a number of seconds")));
+    let count = |flag| matches.value_of(flag).map(|v| v.parse().expect("must give a number"));
+    let budget = syn::Budget {
+        timeout: seconds("timeout"),
+        solver_timeout: seconds("solver-timeout"),
+        max_candidates: count("max-candidates"),
+        max_iterations: count("max-iterations"),
+        jobs: count("jobs").unwrap_or(1),
+        threads: count("threads").unwrap_or(1),
+        stop: None,
+    };
+    let config = syn::Config {
+        strategy: matches.value_of("strategy").map_or(syn::Strategy::TopDown, syn::Strategy::new),
+        backend: matches.value_of("solver").map_or(smt::Backend::Cvc4, smt::Backend::new),
+        prune: matches.is_present("prune"),
+        symbolic: matches.is_present("symbolic-constants"),
+        recursive: matches.is_present("recursive"),
+        explain: verbose,
+    };
+    let portfolio = matches.is_present("portfolio");
+    let json = match matches.value_of("format") {
+        None | Some("smt") => false,
+        Some("json") => true,
+        Some(f) => panic!("unknown format {}", f)
+    };
+
+    if let Some(args) = matches.subcommand_matches("verify") {
+        let spec = fs::read_to_string(args.value_of("spec").expect("must give a spec")).expect("cannot read spec");
+        let solution = fs::read_to_string(args.value_of("solution").expect("must give a solution")).expect("cannot read solution");
+        let mut query = qry::Query::new();
+        query.set_budget(budget);
+        if matches.is_present("cache") || matches.is_present("cache-dir") {
+            query.set_cache(Some(Arc::new(smt::Cache::new(cache_dir))));
+        }
+        process::exit(verify(query, &spec, &solution, config.backend, &mut io::stdout()))
+    }
+
+    if matches.subcommand_matches("repl").is_some() {
+        let mut query = qry::Query::new();
+        query.set_budget(budget);
+        query.set_config(config);
+        if matches.is_present("cache") || matches.is_present("cache-dir") {
+            query.set_cache(Some(Arc::new(smt::Cache::new(cache_dir))));
+        }
+        rpl::run(query, io::stdin().lock(), io::stdout());
+        return
+    }
+
     let mut raw_query = String::new();
 
     if matches.is_present("input") {
@@ -46,21 +138,203 @@ fn main() {
         }
     }
     let mut query = qry::Query::new();
-    query.parse_query(&raw_query).expect("cannot parse file");
-
-    let result = query.solve();
-    if result.is_some() {
-        if matches.is_present("verbose") {
-            query.add_body(query.get_synth().expect("must have function to synthesize").as_str(), result.unwrap());
-            println!("{}", query);
+    query.set_budget(budget);
+    query.set_config(config);
+    if let Some(f) = matches.value_of("trace") {
+        // the racers would write over each other
+        assert!(!portfolio, "cannot trace a portfolio");
+        query.set_recorder(Some(trc::Recorder::new(f)));
+    }
+    if matches.is_present("cache") || matches.is_present("cache-dir") {
+        query.set_cache(Some(Arc::new(smt::Cache::new(cache_dir))));
+    }
+    let mut seeds = matches.value_of("counterexamples").map(|f| fs::read_to_string(f).expect("cannot read counterexamples"));
+    let hints = matches.value_of("hints").map(|f| fs::read_to_string(f).expect("cannot read hints"));
+    let components: Option<Vec<String>> = matches.value_of("components").map(|c| c.split(',').map(|n| n.trim().to_owned()).collect());
+    let all_components = matches.is_present("all-components");
+    let export = matches.value_of("export-counterexamples");
+    query.set_replay(matches.value_of("replay").map(|f| Arc::new(trc::Replay::load(f))));
+    // scripts can pose several problems using push and pop: answer each one at its check-sat
+    let parsed = query.parse_incremental(&raw_query, |query| {
+        let name = query.get_synth().expect("must have function to synthesize");
+        // read against the problem as it is now. Counterexamples carry over to later problems
+        if let Some(seeds) = seeds.take() {
+            let cexs = query.parse_counterexamples(&seeds).expect("cannot parse counterexamples");
+            query.set_counterexamples(cexs);
+        }
+        if let Some(hints) = &hints {
+            let terms = query.parse_terms(hints).expect("cannot parse hints");
+            if let Err(e) = query.set_hints(terms) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        // named ones must be defined by now
+        let chosen = if all_components {
+            query.set_components(query.get_defined())
+        } else if let Some(names) = &components {
+            query.set_components(names.clone())
         } else {
-            let name = query.get_synth().expect("must have function to synthesize");
-            let (params, rsort) = query.peek_ctx().get_decl(&name).expect("declaration not found!").first().expect("ureachable");
-            let args : Vec<String> = params.into_iter().map(|(n, s)| format!("({} {})", n, s)).collect();
-            println!("(define-fun {} ({}) {} {})", name, args.join(" "), rsort.to_string(), result.unwrap());
+            Ok(())
+        };
+        if let Err(e) = chosen {
+            eprintln!("{}", e);
+            process::exit(1);
         }
-    } else {
-        println!("(no-solution)");
+        let (config, outcome) = if portfolio {
+            let (winner, outcome) = query.portfolio(&syn::Config::all());
+            if let (syn::Outcome::Solved(_), false) = (&outcome, json) {
+                println!("; solved by {}", winner);
+            }
+            (winner, outcome)
+        } else {
+            (query.get_config(), query.solve())
+        };
+        if let Some(f) = export {
+            let models: Vec<String> = query.get_counterexamples().iter().map(|c| query.model_to_string(c)).collect();
+            fs::write(f, models.join("\n") + "\n").expect("cannot write counterexamples");
+        }
+        if json {
+            println!("{}", to_json(query, &name, config, &outcome));
+            return
+        }
+        if verbose {
+            for e in &query.get_stats().explanations {
+                println!("; counterexample {}: {}", e.index, e);
+            }
+        }
+        match outcome {
+            syn::Outcome::Solved(body) => {
+                if verbose {
+                    query.add_body(name.as_str(), body);
+                    println!("{}", query);
+                    query.remove_body(name.as_str());
+                } else {
+                    println!("{}", query.solution_to_string(&name, &body));
+                }
+            }
+            syn::Outcome::NoSolution => println!("(no-solution)"),
+            syn::Outcome::Exhausted(limit, partial) => {
+                match &limit {
+                    syn::Limit::Timeout
+                    | syn::Limit::SolverTimeout
+                    | syn::Limit::Stopped => println!("(timeout)"),
+                    syn::Limit::Candidates
+                    | syn::Limit::Iterations => println!("(budget-exhausted)"),
+                    syn::Limit::Failed(e) => println!("(error \"solver failed: {}\")", e.replace('"', "\"\"")),
+                }
+                if verbose {
+                    println!("; gave up ({:?}) after {} candidates, {} iterations and {} counterexamples",
+                        limit, partial.candidates, partial.iterations, partial.counterexamples.len());
+                    if let Some(best) = partial.best {
+                        println!("; best candidate: {}", query.solution_to_string(&name, &best));
+                    }
+                }
+            }
+        }
+    });
+    if let Err(e) = parsed {
+        eprintln!("cannot parse file: {}", e);
+        process::exit(1);
+    }
+}
+
+// check a solution against a spec. The exit code is 0 if it is valid, 1 if it is not, with a
+// counterexample, and 2 if we can't tell: the solver failed, timed out or does not know
+fn verify<W: Write>(mut query: qry::Query, spec: &str, solution: &str, backend: smt::Backend, out: &mut W) -> i32 {
+    if let Err(e) = add_solution(&mut query, spec, solution) {
+        eprintln!("cannot verify: {}", e);
+        return 2
+    }
+    match query.check(backend) {
+        // no model means the spec can't be violated
+        Ok(cex) if cex.is_empty() => {
+            writeln!(out, "valid").expect("cannot write answer");
+            0
+        },
+        Ok(cex) => {
+            writeln!(out, "{}", query.model_to_string(&cex)).expect("cannot write answer");
+            1
+        },
+        Err(e) => {
+            eprintln!("cannot verify: {}", e);
+            2
+        }
+    }
+}
+
+// the spec with the body the solution gives the function to synthesize
+fn add_solution(query: &mut qry::Query, spec: &str, solution: &str) -> Result<(), String> {
+    query.parse_query(spec).map_err(|e| format!("cannot parse spec: {}", e))?;
+    let name = query.get_synth().ok_or("the spec has no function to synthesize")?;
+    let sol = query.parse_answer(solution).map_err(|e| format!("cannot parse solution: {}", e))?;
+    let body = sol.get(&name).ok_or(format!("solution must define {}", name))?;
+    let (params, rsort) = query.peek_ctx().get_decl(&name).expect("synth has to have decl");
+    if query.peek_ctx().with_params(params).check_sort(body).as_ref() != Some(rsort) {
+        return Err(format!("body of {} must be a {}", name, rsort))
+    }
+    query.add_body(&name, Arc::clone(body));
+    Ok(())
+}
+
+// one line of json per problem
+fn to_json(query: &qry::Query, name: &str, config: syn::Config, outcome: &syn::Outcome) -> String {
+    let (status, solution) = match outcome {
+        syn::Outcome::Solved(body) => ("solved", trc::quote(&query.solution_to_string(name, body))),
+        syn::Outcome::NoSolution => ("no-solution", "null".to_owned()),
+        syn::Outcome::Exhausted(syn::Limit::Candidates, _)
+        | syn::Outcome::Exhausted(syn::Limit::Iterations, _) => ("budget-exhausted", "null".to_owned()),
+        syn::Outcome::Exhausted(syn::Limit::Failed(_), _) => ("failed", "null".to_owned()),
+        syn::Outcome::Exhausted(_, _) => ("timeout", "null".to_owned()),
     };
-    
-}
\ No newline at end of file
+    let stats = query.get_stats();
+    format!("{{\"status\": \"{}\", \"solution\": {}, \"config\": \"{}\", \"candidates\": {}, \"counterexamples\": {}, \"solver_calls\": {}, \"pruned\": {}, \"time\": {{\"enumeration\": {}, \"verification\": {}, \"total\": {}}}}}",
+        status, solution, config, stats.candidates, stats.counterexamples, stats.solver_calls, stats.pruned,
+        stats.enumeration.as_secs_f64(), stats.verification.as_secs_f64(), stats.total.as_secs_f64())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::sync::Arc;
+    use crate::{qry, smt};
+    use super::verify;
+
+    #[test]
+    fn test_verify() {
+        // the solver's answers come from the cache, so none needs to be installed
+        let dir = env::temp_dir().join(format!("llama-{}-verify", std::process::id()));
+        let cache = Arc::new(smt::Cache::new(dir.clone()));
+        let spec = "(set-logic QF_LIA)\n(synth-blocking-fun f ((x Int)) Bool)\n(declare-const x Int)\n(assert (not (= (f x) (>= x 3))))\n(check-sat)";
+        let run = |solution: &str, answer: &str| {
+            let mut query = qry::Query::new();
+            query.parse_query(spec).expect("cannot parse spec");
+            let sol = query.parse_answer(solution).expect("cannot parse solution");
+            query.add_body("f", Arc::clone(&sol["f"]));
+            cache.put(smt::Backend::Cvc4, &query.to_string(), answer);
+            let mut query = qry::Query::new();
+            query.set_cache(Some(Arc::clone(&cache)));
+            let mut out = vec![];
+            let code = verify(query, spec, solution, smt::Backend::Cvc4, &mut out);
+            (code, String::from_utf8(out).expect("answers are text"))
+        };
+        assert!(run("(define-fun f ((x Int)) Bool (>= x 3))", "unsat\n") == (0, "valid\n".to_owned()));
+        let (code, out) = run("(define-fun f ((x Int)) Bool (> x 3))", "sat\n(model\n(define-fun x () Int 3)\n)\n");
+        assert!(code == 1 && out.contains("(define-fun x () Int 3)"));
+        assert!(run("(define-fun f ((x Int)) Bool (> x 4))", "unknown\n") == (2, String::new()));
+
+        // solutions that do not fit the spec are errors, before any solver is asked
+        let wrong = |solution: &str| {
+            let mut out = vec![];
+            let code = verify(qry::Query::new(), spec, solution, smt::Backend::Cvc4, &mut out);
+            (code, out.is_empty())
+        };
+        assert!(wrong("(define-fun f ((x Int)) Int x)") == (2, true));
+        assert!(wrong("(define-fun f ((x Int) (y Int)) Bool (>= x y))") == (2, true));
+        assert!(wrong("(define-fun g ((x Int)) Bool (>= x 3))") == (2, true));
+        assert!(wrong("(define-fun f ((x Int)) Bool (+ x 3))") == (2, true));
+        assert!(wrong("(define-fun f ((x Int)) Bool") == (2, true));
+        cache.clear();
+        std::fs::remove_dir(&dir).expect("cache should be empty");
+    }
+}
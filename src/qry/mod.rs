@@ -1,39 +1,77 @@
 use core::slice::{self};
 use std::collections::{HashMap};
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use pest::Parser;
 use pest::error::Error;
 use pest::iterators::Pair;
 
 use crate::ast::{Term, Symbol};
-use crate::ctx::{Context, Logic, Sort, Solution};
-use crate::rwr::rename;
+use crate::ctx::{Constructor, Context, Logic, Sort, Solution};
+use crate::rwr::{rename, lambda_to_store};
+use crate::syn::{Budget, Config, Stats};
+use crate::trc::{Recorder, Replay};
+use crate::smt::Cache;
 
 
+#[derive(Clone)]
 pub enum Command {
     SetLogic,
+    Datatypes(Vec<String>),
     Declare(String),
     Define(String),
+    // functions defined together, each possibly calling the others
+    DefineRec(Vec<String>),
     Synth(String),
-    Assert(Rc<Term>),
+    Assert(Arc<Term>),
     CheckSat,
     GetModel,
     Push,
     Pop,
 }
 
+// a datatype name and its constructors, each with its (selector, sort) fields
+pub type DatatypeDecl<'a> = (&'a str, Vec<(&'a str, Vec<(&'a str, &'a str)>)>);
+// a function name, its (name, sort) parameters, its sort and its body
+pub type FunDecl<'a> = (&'a str, Vec<(&'a str, &'a str)>, &'a str, Arc<Term>);
+
+#[derive(Clone)]
 pub struct Query {
     script: Vec<Command>,
-    ctx: Context
+    ctx: Context,
+    budget: Budget,
+    config: Config,
+    // what the last call to solve did
+    stats: Stats,
+    // where to write down what solve does
+    recorder: Option<Recorder>,
+    // recorded solver answers to use instead of the solver
+    replay: Option<Arc<Replay>>,
+    // solver answers from earlier runs
+    cache: Option<Arc<Cache>>,
+    // candidate bodies to try before searching
+    hints: Vec<Arc<Term>>,
+    // defined functions the grammar can build candidates with
+    components: Vec<String>,
+    // the counterexamples to start the next call to solve with, and that it finished with
+    counterexamples: Vec<Solution>
 }
 
 impl Query {
     pub fn new() -> Query {
         let query = Query {
             script: vec![],
-            ctx: Context::new()
+            ctx: Context::new(),
+            budget: Budget::default(),
+            config: Config::default(),
+            stats: Stats::default(),
+            recorder: None,
+            replay: None,
+            cache: None,
+            hints: vec![],
+            components: vec![],
+            counterexamples: vec![]
         };
         query
     }
@@ -42,6 +80,93 @@ impl Query {
         &self.ctx
     }
 
+    pub fn get_budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    pub fn get_config(&self) -> Config {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    pub fn get_stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn set_stats(&mut self, stats: Stats) {
+        self.stats = stats;
+    }
+
+    pub fn get_recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    pub fn get_replay(&self) -> Option<Arc<Replay>> {
+        self.replay.clone()
+    }
+
+    pub fn set_replay(&mut self, replay: Option<Arc<Replay>>) {
+        self.replay = replay;
+    }
+
+    pub fn get_cache(&self) -> Option<Arc<Cache>> {
+        self.cache.clone()
+    }
+
+    pub fn set_cache(&mut self, cache: Option<Arc<Cache>>) {
+        self.cache = cache;
+    }
+
+    pub fn get_hints(&self) -> &[Arc<Term>] {
+        &self.hints
+    }
+
+    // hints are bodies for the function to synthesize, so they must type check as one
+    pub fn set_hints(&mut self, hints: Vec<Arc<Term>>) -> Result<(), String> {
+        let name = self.get_synth().ok_or("hints need a function to synthesize")?;
+        let (params, rsort) = self.ctx.get_decl(&name).expect("synth has to have decl");
+        let ctx = self.ctx.with_params(params);
+        for h in &hints {
+            if ctx.check_sort(h).as_ref() != Some(rsort) {
+                return Err(format!("hint {} must be a {}", h, rsort))
+            }
+        }
+        self.hints = hints;
+        Ok(())
+    }
+
+    pub fn get_components(&self) -> &[String] {
+        &self.components
+    }
+
+    pub fn set_components(&mut self, components: Vec<String>) -> Result<(), String> {
+        let defined = self.get_defined();
+        if let Some(c) = components.iter().find(|c| !defined.contains(c)) {
+            return Err(format!("{} is not a defined function", c))
+        }
+        self.components = components;
+        Ok(())
+    }
+
+    pub fn get_counterexamples(&self) -> &[Solution] {
+        &self.counterexamples
+    }
+
+    pub fn set_counterexamples(&mut self, counterexamples: Vec<Solution>) {
+        self.counterexamples = counterexamples;
+    }
+
     pub fn set_logic(&mut self, logic: &str) {
         let l = Logic::to_logic(logic);
         self.ctx.update_logic(&l);
@@ -63,6 +188,55 @@ impl Query {
         self.declare_fun(name, vec![], rsort)
     }
 
+    pub fn declare_datatypes(&mut self, datatypes: Vec<DatatypeDecl>) {
+        let names = datatypes.iter().map(|(n, _)| (*n).to_owned()).collect();
+        let datatypes = datatypes
+            .into_iter()
+            .map(|(n, ctors)| (n.to_owned(), ctors
+                .into_iter()
+                .map(|(c, fields)| (c.to_owned(), fields
+                    .into_iter()
+                    .map(|(f, s)| (f.to_owned(), Sort::new(s)))
+                    .collect()))
+                .collect()))
+            .collect();
+        self.ctx.add_datatypes(datatypes);
+        self.script.push(Command::Datatypes(names));
+    }
+
+    // why these datatypes cannot be declared, if they cannot
+    fn check_datatypes(&self, datatypes: &[DatatypeDecl]) -> Result<(), String> {
+        if !self.ctx.get_logic().dt {
+            return Err("datatypes not supported by the logic".to_owned())
+        }
+        for (i, (name, _)) in datatypes.iter().enumerate() {
+            if self.ctx.get_datatype(name).is_some() || datatypes[..i].iter().any(|(n, _)| n == name) {
+                return Err(format!("{} is already declared", name))
+            }
+        }
+        // the fields may be of any of the datatypes
+        let mut ctx = self.ctx.clone();
+        ctx.add_datatypes(datatypes.iter().map(|(n, _)| ((*n).to_owned(), vec![])).collect());
+        let mut functions: Vec<String> = vec![];
+        for (_, ctors) in datatypes {
+            for (cname, fields) in ctors {
+                let names = std::iter::once(cname.to_string())
+                    .chain(std::iter::once(format!("is-{}", cname)))
+                    .chain(fields.iter().map(|(f, _)| f.to_string()));
+                for n in names {
+                    if ctx.get_decl(&n).is_some() || ctx.get_builtin(&n).is_some() || functions.contains(&n) {
+                        return Err(format!("{} is already declared", n))
+                    }
+                    functions.push(n);
+                }
+                if let Some((_, s)) = fields.iter().find(|(_, s)| !Sort::parse(s).is_some_and(|sort| ctx.supports(&sort))) {
+                    return Err(format!("sort {} not supported by the logic", s))
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn define_synth(&mut self, name: &str, params: Vec<(&str, &str)>, rsort: &str) {
         assert!(self.script.iter().fold(true, |acc, r| match r {Command::Synth(_) => false, _ => acc && true}));
         let params: Vec<(String, Sort)> = params
@@ -73,7 +247,7 @@ impl Query {
         self.script.push(Command::Synth(name.to_owned()));
     }
 
-    pub fn define_fun(&mut self, name: &str, params: Vec<(&str, &str)>, rsort: &str, body: Rc<Term>) {
+    pub fn define_fun(&mut self, name: &str, params: Vec<(&str, &str)>, rsort: &str, body: Arc<Term>) {
         let params: Vec<(String, Sort)> = params
             .into_iter()
             .map(|(n, s)| (n.to_owned(), Sort::new(s)))
@@ -83,7 +257,40 @@ impl Query {
         self.script.push(Command::Define(name.to_owned()));
     }
 
-    pub fn assert(&mut self, node: Rc<Term>) {
+    // declare all the functions before checking any body, since they may call each other.
+    // Nothing is defined if a body does not type check
+    pub fn define_funs_rec(&mut self, defs: Vec<FunDecl>) -> Result<(), String> {
+        let mut ctx = self.ctx.clone();
+        let mut names = vec![];
+        let mut bodies = vec![];
+        for (name, params, rsort, body) in defs {
+            let params: Vec<(String, Sort)> = params
+                .into_iter()
+                .map(|(n, s)| (n.to_owned(), Sort::new(s)))
+                .collect();
+            ctx.add_decl(name, params, Sort::new(rsort));
+            names.push(name.to_owned());
+            bodies.push(body);
+        }
+        for (name, body) in names.iter().zip(bodies.iter()) {
+            let (params, rsort) = ctx.get_decl(name).expect("unreachable");
+            if ctx.with_params(params).check_sort(body).as_ref() != Some(rsort) {
+                return Err(format!("body of {} must have sort {}", name, rsort))
+            }
+        }
+        self.ctx = ctx;
+        for (name, body) in names.iter().zip(bodies) {
+            self.ctx.add_body(name, body);
+        }
+        // the solver can only take recursive definitions in a logic with quantifiers
+        let mut q = Logic::new();
+        q.q = true;
+        self.ctx.update_logic(&q);
+        self.script.push(Command::DefineRec(names));
+        Ok(())
+    }
+
+    pub fn assert(&mut self, node: Arc<Term>) {
         self.script.push(Command::Assert(node));
     }
 
@@ -95,7 +302,24 @@ impl Query {
         self.script.push(Command::GetModel);
     }
 
-    pub fn add_body(&mut self, name: &str, body: Rc<Term>) {
+    pub fn push(&mut self) {
+        self.ctx.push();
+        self.script.push(Command::Push);
+    }
+
+    // how many pushes there are to pop
+    pub fn get_levels(&self) -> usize {
+        self.script.iter().filter(|c| matches!(c, Command::Push)).count()
+    }
+
+    // forget everything since the matching push
+    pub fn pop(&mut self) {
+        let idx = self.script.iter().rposition(|c| matches!(c, Command::Push)).expect("pop without matching push");
+        self.script.truncate(idx);
+        self.ctx.pop();
+    }
+
+    pub fn add_body(&mut self, name: &str, body: Arc<Term>) {
         self.ctx.add_body(name, body);
     }
 
@@ -103,6 +327,26 @@ impl Query {
         self.ctx.remove_body(name);
     }
 
+    // the same theory with nothing to synthesize and nothing asserted, to ask the solver other questions
+    pub fn background(&self) -> Query {
+        let mut query = self.clone();
+        query.script.retain(|c| matches!(c, Command::SetLogic | Command::Datatypes(_) | Command::Declare(_) | Command::Define(_) | Command::DefineRec(_)));
+        query
+    }
+
+    // the functions the script defines, in order
+    pub fn get_defined(&self) -> Vec<String> {
+        let mut names = vec![];
+        for c in &self.script {
+            match c {
+                Command::Define(n) => names.push(n.clone()),
+                Command::DefineRec(ns) => names.extend(ns.iter().cloned()),
+                _ => ()
+            }
+        }
+        names
+    }
+
     pub fn get_synth(&self) -> Option<String> {
         for c in &self.script {
             match c {
@@ -129,16 +373,26 @@ impl<'a> IntoIterator for &'a Query {
 struct SynthParser;
 
 impl Query {
-    fn parse_fapp(&self, pair: Pair<Rule>) -> Result<Rc<Term>, Error<Rule>> {
+    fn parse_fapp(&self, pair: Pair<Rule>) -> Result<Arc<Term>, Error<Rule>> {
         match pair.as_rule() {
             Rule::fapp => {
                 let mut inner = pair.into_inner();
-                let func = inner.next().unwrap().as_span().as_str();
-                let mut args : Vec<Rc<Term>> = vec! [];
+                let head = inner.next().unwrap();
+                let mut args : Vec<Arc<Term>> = vec! [];
                 for i in inner {
                     args.push(self.parse_fapp(i)?)
                 }
-                Ok(self.mk_app(func, args))
+                match head.as_rule() {
+                    Rule::tester => {
+                        let cname = head.into_inner().next().unwrap().as_span().as_str();
+                        Ok(self.mk_app(format!("is-{}", cname).as_str(), args))
+                    }
+                    Rule::constarr => {
+                        let asort = Sort::new(head.into_inner().next().unwrap().as_span().as_str());
+                        Ok(Term::mk_app(Symbol::ConstArray(asort), args))
+                    }
+                    _ => Ok(self.mk_app(head.as_span().as_str(), args))
+                }
             },
             _ => Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
                         message: "expecting function application!".to_owned(),
@@ -147,13 +401,91 @@ impl Query {
     }
 
     fn parse_command(&mut self, pair: Pair<Rule>) -> Result<(), Error<Rule>> {
+        // each function can be declared once, and interpreted ones not at all
+        let names: Vec<Pair<Rule>> = match pair.as_rule() {
+            Rule::declare | Rule::synth | Rule::define | Rule::definerec => pair.clone().into_inner().take(1).collect(),
+            Rule::definesrec => pair.clone().into_inner().filter(|r| r.as_rule() == Rule::fundecl).filter_map(|r| r.into_inner().next()).collect(),
+            _ => vec![]
+        };
+        for n in names {
+            let name = n.as_span().as_str();
+            if self.ctx.get_decl(name).is_some() || self.ctx.get_builtin(name).is_some() {
+                return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                    message: format!("{} is already declared", name),
+                }, n.as_span()))
+            }
+        }
+        // sorts have to be in the logic, except in datatypes, which may use the ones they declare
+        if pair.as_rule() != Rule::datatype && pair.as_rule() != Rule::datatypes {
+            for s in pair.clone().into_inner().flatten().filter(|r| r.as_rule() == Rule::sort) {
+                if !Sort::parse(s.as_str()).is_some_and(|sort| self.ctx.supports(&sort)) {
+                    return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: format!("sort {} not supported by the logic", s.as_str()),
+                    }, s.as_span()))
+                }
+            }
+        }
+        // and only defined functions or ones to synthesize take parameters without UF
+        let params = match pair.as_rule() {
+            Rule::declare => pair.clone().into_inner().filter(|r| r.as_rule() == Rule::sort).count() > 1,
+            Rule::define | Rule::definerec => pair.clone().into_inner().any(|r| r.as_rule() == Rule::param),
+            Rule::definesrec => pair.clone().into_inner()
+                .filter(|r| r.as_rule() == Rule::fundecl)
+                .any(|r| r.into_inner().any(|r| r.as_rule() == Rule::param)),
+            _ => false
+        };
+        if params && !self.ctx.get_logic().uf {
+            return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                message: "functions with parameters not supported by the logic".to_owned(),
+            }, pair.as_span()))
+        }
         match pair.as_rule() {
             Rule::setlogic => {
+                let span = pair.as_span();
                 let mut inner = pair.into_inner();
                 let name = inner.next().unwrap().as_span().as_str();
+                if Logic::parse(name).is_none() {
+                    return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: format!("logic {} not supported", name),
+                    }, span))
+                }
                 self.set_logic(&name);
                 Ok(())
             }
+            Rule::datatype => {
+                let span = pair.as_span();
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap().as_span().as_str();
+                let ctors = self.parse_dtdecl(inner.next().unwrap());
+                let datatypes = vec![(name, ctors)];
+                self.check_datatypes(&datatypes).map_err(|message| Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                    message,
+                }, span))?;
+                self.declare_datatypes(datatypes);
+                Ok(())
+            }
+            Rule::datatypes => {
+                let span = pair.as_span();
+                let mut names = vec! [];
+                let mut decls = vec! [];
+                for r in pair.into_inner() {
+                    match r.as_rule() {
+                        Rule::sortdecl => names.push(r.into_inner().next().unwrap().as_span().as_str()),
+                        _ => decls.push(self.parse_dtdecl(r)),
+                    }
+                }
+                if names.len() != decls.len() {
+                    return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: "need exactly one declaration per datatype".to_owned(),
+                    }, span));
+                }
+                let datatypes: Vec<DatatypeDecl> = names.into_iter().zip(decls).collect();
+                self.check_datatypes(&datatypes).map_err(|message| Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                    message,
+                }, span))?;
+                self.declare_datatypes(datatypes);
+                Ok(())
+            }
             Rule::declare => { 
                 let mut inner = pair.into_inner();
                 let name = inner.next().unwrap().as_span().as_str();
@@ -168,6 +500,11 @@ impl Query {
                 Ok(())
             }
             Rule::synth => { 
+                if self.get_synth().is_some() {
+                    return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: "there is already a function to synthesize".to_owned(),
+                    }, pair.as_span()))
+                }
                 let mut inner = pair.into_inner();
                 let name = inner.next().unwrap().as_span().as_str();
 
@@ -212,10 +549,78 @@ impl Query {
                 self.define_fun(&name, params, rsort, body);
                 Ok(())
             }
+            Rule::definerec => {
+                let span = pair.as_span();
+                let (name, params, rsort, body) = self.parse_fundecl(pair.into_inner());
+                let body = self.parse_fapp(body)?;
+                self.define_funs_rec(vec![(name, params, rsort, body)]).map_err(|message| Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                    message,
+                }, span))
+            }
+            Rule::definesrec => {
+                let span = pair.as_span();
+                let mut decls = vec![];
+                let mut bodies = vec![];
+                for r in pair.into_inner() {
+                    match r.as_rule() {
+                        Rule::fundecl => decls.push(r),
+                        _ => bodies.push(r),
+                    }
+                }
+                if decls.len() != bodies.len() {
+                    return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: "need exactly one body per function".to_owned(),
+                    }, span));
+                }
+                let mut defs = vec![];
+                for (d, b) in decls.into_iter().zip(bodies) {
+                    let mut inner: Vec<Pair<Rule>> = d.into_inner().collect();
+                    inner.push(b);
+                    let (name, params, rsort, body) = self.parse_fundecl(inner.into_iter());
+                    defs.push((name, params, rsort, self.parse_fapp(body)?));
+                }
+                self.define_funs_rec(defs).map_err(|message| Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                    message,
+                }, span))
+            }
+            Rule::push => {
+                let span = pair.as_span();
+                match parse_level(pair) {
+                    Some(n) => {
+                        for _ in 0..n {
+                            self.push();
+                        }
+                        Ok(())
+                    },
+                    None => Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: "level is too big".to_owned(),
+                    }, span))
+                }
+            }
+            Rule::pop => {
+                let span = pair.as_span();
+                match parse_level(pair) {
+                    Some(n) if n <= self.get_levels() => {
+                        for _ in 0..n {
+                            self.pop();
+                        }
+                        Ok(())
+                    },
+                    _ => Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: "pop without matching push".to_owned(),
+                    }, span))
+                }
+            }
             Rule::checksat => {self.check_sat(); Ok(())},
             Rule::getmodel => {self.get_model(); Ok(())},
             Rule::assert => {
+                let span = pair.as_span();
                 let node = self.parse_fapp(pair.into_inner().next().unwrap())?;
+                if self.ctx.check_sort(&node) != Some(Sort::Bool) {
+                    return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                        message: "assertion must be a well-sorted Bool".to_owned(),
+                    }, span))
+                }
                 self.assert(node);
                 Ok(())
             },
@@ -225,12 +630,42 @@ impl Query {
         }
     }
 
-    fn parse_model(&self, pair: Pair<Rule>) -> Result<(String, (Vec<(String, Sort)>, Sort, Rc<Term>)), Error<Rule>> {
+    // a name, parameters, a sort and the body still to parse
+    fn parse_fundecl<'a>(&self, inner: impl Iterator<Item = Pair<'a, Rule>>) -> (&'a str, Vec<(&'a str, &'a str)>, &'a str, Pair<'a, Rule>) {
+        let mut defn: Vec<Pair<Rule>> = inner.collect();
+        let body = defn.pop().unwrap();
+        let rsort = defn.pop().unwrap().as_span().as_str();
+        let name = defn.remove(0).as_span().as_str();
+        let params = defn.into_iter().map(|r| {
+            let mut inner = r.into_inner();
+            let name = inner.next().unwrap().as_span().as_str();
+            let sort = inner.next().unwrap().as_span().as_str();
+            (name, sort)
+        }).collect();
+        (name, params, rsort, body)
+    }
+
+    fn parse_dtdecl<'a>(&self, pair: Pair<'a, Rule>) -> Vec<(&'a str, Vec<(&'a str, &'a str)>)> {
+        pair.into_inner().map(|c| {
+            let mut inner = c.into_inner();
+            let name = inner.next().unwrap().as_span().as_str();
+            let fields = inner.map(|r| {
+                let mut inner = r.into_inner();
+                let name = inner.next().unwrap().as_span().as_str();
+                let sort = inner.next().unwrap().as_span().as_str();
+                (name, sort)
+            }).collect();
+            (name, fields)
+        }).collect()
+    }
+
+    fn parse_model(&self, pair: Pair<Rule>) -> Result<(String, (Vec<(String, Sort)>, Sort, Arc<Term>)), Error<Rule>> {
         match pair.as_rule() {
             // this is slightly different from command parsing above
             // - we do not define
             // - we produce String rather than &str
-            Rule::define => { 
+            // a solution may also be recursive
+            Rule::define | Rule::definerec => { 
                 let mut inner = pair.into_inner();
                 let name = inner.next().unwrap().as_span().as_str().to_owned();
 
@@ -239,8 +674,25 @@ impl Query {
                     defn.push(s);
                 }
 
-                let body = self.parse_fapp(defn.pop().unwrap())?;
-                let rsort = defn.pop().unwrap().as_span().as_str();
+                let body = defn.pop().unwrap();
+                let rsort = Sort::new(defn.pop().unwrap().as_span().as_str());
+                let body = match body.as_rule() {
+                    // solvers may give array values as lambdas, we only deal in store chains
+                    Rule::lambda => {
+                        let span = body.as_span();
+                        let mut inner = body.into_inner();
+                        let mut param = inner.next().unwrap().into_inner();
+                        let pname = param.next().unwrap().as_span().as_str();
+                        let lbody = self.parse_fapp(inner.next().unwrap())?;
+                        match lambda_to_store(&rsort, pname, &lbody) {
+                            Some(t) => t,
+                            None => return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                                message: "lambda is not a finite array!".to_owned(),
+                            }, span))
+                        }
+                    }
+                    _ => self.parse_fapp(body)?
+                };
                 let params = defn.into_iter().map(|r| match r.as_rule() {
                     Rule::param => {
                         let mut inner = r.into_inner();
@@ -250,7 +702,7 @@ impl Query {
                     },
                     _ => panic!("must be a param rule!")
                 }).collect();
-                Ok((name, (params, Sort::new(rsort), body)))
+                Ok((name, (params, rsort, body)))
             }
             _ => Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
                         message: "command not supported!".to_owned(),
@@ -259,10 +711,19 @@ impl Query {
     }
 
     pub fn parse_query(&mut self, file: &str) -> Result<(), Error<Rule>> {
-        let syntax = SynthParser::parse(Rule::query, file).expect("failed to read!");    
+        self.parse_incremental(file, |_| ())
+    }
+
+    // parse a script that may pose several problems, calling on_check_sat on the current one at every check-sat
+    pub fn parse_incremental<F: FnMut(&mut Query)>(&mut self, file: &str, mut on_check_sat: F) -> Result<(), Error<Rule>> {
+        let syntax = SynthParser::parse(Rule::query, file)?;
         let mut empty = false;
         for r in syntax {
+            let check = r.as_rule() == Rule::checksat;
             self.parse_command(r)?;
+            if check {
+                on_check_sat(self);
+            }
             empty = true
         };
         // self.well_formed();
@@ -271,21 +732,29 @@ impl Query {
     }
 
     pub fn parse_answer(&self, file: &str) -> Result<Solution, Error<Rule>> {
-        let syntax = SynthParser::parse(Rule::result, file).expect("failed to read!");
+        // e.g. unknown, which is no answer
+        let syntax = SynthParser::parse(Rule::result, file)?;
         let mut sol = Solution::new();
         for r in syntax {
+            let span = r.as_span();
             let (name, (params, rsort, body)) = self.parse_model(r)?;
-            
-            let (exp_params, exp_rsort) = self.peek_ctx()
-                .get_decl(name.as_str())
-                .expect("definition must have been declared!")
-                .first().expect("unreachable");
-
-            assert!(exp_rsort == &rsort, "expected return sorts must be the same");
-            assert!(exp_params.len() == params.len(), "paramaters must match in length");
+
+            // the definition has to be of something declared, and look like its declaration
+            let message = match self.peek_ctx().get_decl(name.as_str()) {
+                None => Some(format!("{} is not declared", name)),
+                Some((_, exp_rsort)) if exp_rsort != &rsort => Some(format!("{} must return a {}", name, exp_rsort)),
+                Some((exp_params, _)) if exp_params.len() != params.len() => Some(format!("{} takes {} parameters", name, exp_params.len())),
+                Some((exp_params, _)) => exp_params.iter().zip(params.iter()).find(|(e, p)| e.1 != p.1)
+                    .map(|(e, p)| format!("parameter {} of {} must be a {}", p.0, name, e.1)),
+            };
+            if let Some(message) = message {
+                return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError{
+                    message,
+                }, span))
+            }
+            let (exp_params, _) = self.peek_ctx().get_decl(name.as_str()).expect("unreachable");
             let mut rewrite: HashMap<String, String> = HashMap::new();
             for i in 0..params.len() {
-                assert!(params[i].1 == exp_params[i].1, "paramater sorts must match");
                 rewrite.insert(params[i].0.clone(), exp_params[i].0.clone());
             }
             let nbody = rename(&rewrite, &body);
@@ -293,14 +762,88 @@ impl Query {
         };
         Ok(sol)
     }
+
+    // a file of models, each a counterexample. The spec may have changed since they were found,
+    // so definitions of symbols that are no longer declared, or not like this, are left out
+    pub fn parse_counterexamples(&self, file: &str) -> Result<Vec<Solution>, Error<Rule>> {
+        let syntax = SynthParser::parse(Rule::counterexamples, file)?;
+        let mut sols = vec![];
+        for cex in syntax.filter(|r| r.as_rule() == Rule::cex) {
+            let mut sol = Solution::new();
+            for r in cex.into_inner() {
+                let (name, (params, rsort, body)) = self.parse_model(r)?;
+                match self.peek_ctx().get_decl(name.as_str()) {
+                    Some((exp_params, exp_rsort)) if exp_rsort == &rsort
+                        && exp_params.len() == params.len()
+                        && exp_params.iter().zip(params.iter()).all(|(e, p)| e.1 == p.1) => {
+                        let rewrite = params.iter().zip(exp_params.iter()).map(|(p, e)| (p.0.clone(), e.0.clone())).collect();
+                        sol.insert(name, rename(&rewrite, &body));
+                    }
+                    _ => debug!("leaving out {}, it does not match the spec", name)
+                }
+            }
+            sols.push(sol);
+        }
+        Ok(sols)
+    }
+
+    // a file of terms, like candidate bodies
+    pub fn parse_terms(&self, file: &str) -> Result<Vec<Arc<Term>>, Error<Rule>> {
+        let syntax = SynthParser::parse(Rule::terms, file)?;
+        syntax.filter(|r| r.as_rule() == Rule::fapp).map(|r| self.parse_fapp(r)).collect()
+    }
+
+    // the function to synthesize with this body, the way it is given as a solution
+    pub fn solution_to_string(&self, name: &str, body: &Term) -> String {
+        let (params, rsort) = self.ctx.get_decl(name).expect("declaration not found!");
+        let args : Vec<String> = params.iter().map(|(n, s)| format!("({} {})", n, s)).collect();
+        let define = if body.calls(name) {"define-fun-rec"} else {"define-fun"};
+        format!("({} {} ({}) {} {})", define, name, args.join(" "), rsort, body)
+    }
+
+    // a counterexample the way solvers write models, so parse_counterexamples can read it back
+    pub fn model_to_string(&self, sol: &Solution) -> String {
+        let mut names: Vec<&String> = sol.keys().collect();
+        names.sort();
+        let defs: Vec<String> = names.into_iter().map(|name| {
+            let (params, rsort) = self.ctx.get_decl(name).expect("declaration not found!");
+            let args : Vec<String> = params.iter().map(|(n, s)| format!("({} {})", n, s)).collect();
+            format!("  (define-fun {} ({}) {} {})", name, args.join(" "), rsort, sol[name])
+        }).collect();
+        defs.iter().fold("(model".to_owned(), |acc, d| acc + "\n" + d) + "\n)"
+    }
 }
 
 impl Query {
+    // does the body given to the function to synthesize call it
+    fn recursive_synth(&self) -> bool {
+        self.get_synth().is_some_and(|n| self.ctx.get_body(&n).is_some_and(|b| b.calls(&n)))
+    }
+
     fn command_to_string(&self, c : &Command) -> String {
         match c {
+            // like recursive definitions in the spec, a recursive body needs quantifiers
+            Command::SetLogic if self.recursive_synth() => {
+                let mut logic = self.ctx.get_logic().clone();
+                logic.q = true;
+                format!("(set-logic {})", logic)
+            },
             Command::SetLogic => format!("(set-logic {})", self.ctx.get_logic()),
+            Command::Datatypes(names) => {
+                let decls : Vec<String> = names.iter().map(|n| {
+                    let ctors = self.ctx.get_datatype(n).expect("datatype not found!");
+                    let ctors : Vec<String> = ctors.iter().map(constructor_to_string).collect();
+                    format!("({})", ctors.join(" "))
+                }).collect();
+                if names.len() == 1 {
+                    format!("(declare-datatype {} {})", names[0], decls[0])
+                } else {
+                    let sorts : Vec<String> = names.iter().map(|n| format!("({} 0)", n)).collect();
+                    format!("(declare-datatypes ({}) ({}))", sorts.join(" "), decls.join(" "))
+                }
+            },
             Command::Declare(name) => {
-                let (params, rsort) = self.ctx.get_decl(&name).expect("declaration not found!").first().expect("ureachable");
+                let (params, rsort) = self.ctx.get_decl(&name).expect("declaration not found!");
                 let args : Vec<String> = params.into_iter().map(|(_, s)| s.to_string()).collect();
                 if args.len() > 0 {
                     format!("(declare-fun {} ({}) {})", name, args.join(" "), rsort.to_string())
@@ -309,17 +852,32 @@ impl Query {
                 }
             },
             Command::Define(name) => {
-                let (params, rsort) = self.ctx.get_decl(&name).expect("declaration not found!").first().expect("ureachable");
+                let (params, rsort) = self.ctx.get_decl(&name).expect("declaration not found!");
                 let body = self.ctx.get_body(&name).expect("definition body not found");
                 let args : Vec<String> = params.into_iter().map(|(n, s)| format!("({} {})", n, s)).collect();
                 format!("(define-fun {} ({}) {} {})", name, args.join(" "), rsort.to_string(), body)
             },
+            Command::DefineRec(names) => {
+                let decls : Vec<String> = names.iter().map(|n| {
+                    let (params, rsort) = self.ctx.get_decl(n).expect("declaration not found!");
+                    let args : Vec<String> = params.iter().map(|(n, s)| format!("({} {})", n, s)).collect();
+                    format!("{} ({}) {}", n, args.join(" "), rsort)
+                }).collect();
+                let bodies : Vec<String> = names.iter().map(|n| self.ctx.get_body(n).expect("definition body not found").to_string()).collect();
+                if names.len() == 1 {
+                    format!("(define-fun-rec {} {})", decls[0], bodies[0])
+                } else {
+                    let decls : Vec<String> = decls.iter().map(|d| format!("({})", d)).collect();
+                    format!("(define-funs-rec ({}) ({}))", decls.join(" "), bodies.join(" "))
+                }
+            },
             Command::Synth(name) => {
-                let (params, rsort) = self.ctx.get_decl(&name).expect("declaration not found!").first().expect("ureachable");
+                let (params, rsort) = self.ctx.get_decl(&name).expect("declaration not found!");
                 match self.ctx.get_body(&name) {
                     Some(b) => {
                         let args : Vec<String> = params.into_iter().map(|(n, s)| format!("({} {})", n, s)).collect();
-                        format!("(define-fun {} ({}) {} {})", name, args.join(" "), rsort.to_string(), b)
+                        let define = if b.calls(name) {"define-fun-rec"} else {"define-fun"};
+                        format!("({} {} ({}) {} {})", define, name, args.join(" "), rsort.to_string(), b)
                     },
                     None => {
                         let args : Vec<String> = params.into_iter().map(|(n, s)| format!("({} {})", n, s)).collect();
@@ -336,6 +894,20 @@ impl Query {
     }
 }
 
+// (push) and (pop) default to one level
+// none when the numeral is too big to count pushes with
+fn parse_level(pair: Pair<Rule>) -> Option<usize> {
+    match pair.into_inner().next() {
+        Some(n) => n.as_span().as_str().parse().ok(),
+        None => Some(1)
+    }
+}
+
+fn constructor_to_string((name, fields): &Constructor) -> String {
+    let fields : Vec<String> = fields.iter().map(|(n, s)| format!(" ({} {})", n, s)).collect();
+    format!("({}{})", name, fields.concat())
+}
+
 impl fmt::Display for Query {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let q_iter = self.into_iter();
@@ -349,29 +921,26 @@ impl Query {
         self.into_iter().fold(true, |r, c| r && 
         match c {
             Command::SetLogic => true,
+            Command::Datatypes(_) => true,
             Command::Declare(n) => self.ctx.get_decl(n.as_str()).is_some(),
             Command::Define(n) => {
-                let sigs = self.ctx.get_decl(n.as_str()).expect("definition must have unique declaration");
-                assert!(sigs.len() == 1);
-                let (params, rsort) = sigs.first().expect("must have one definition");
-                let mut ctx = Context::new();
-                ctx.update_logic(self.ctx.get_logic());
+                let (params, rsort) = self.ctx.get_decl(n.as_str()).expect("definition must have a declaration");
+                let mut ctx = self.ctx.fresh();
                 for (n, s) in params {
-                    ctx.add_decl(n.as_str(), vec![], *s);
+                    ctx.add_decl(n.as_str(), vec![], s.clone());
                 }
                 let body = self.ctx.get_body(n).expect("definition must have body");
                 rsort == &ctx.check_sort(body).expect("body must be well formed")
             },
+            // bodies are checked when defined
+            Command::DefineRec(_) => true,
             Command::Synth(n) => {
-                let sigs = self.ctx.get_decl(n.as_str()).expect("definition must have unique declaration");
-                assert!(sigs.len() == 1);
-                let (params, rsort) = sigs.first().expect("must have one definition");
-                let mut ctx = Context::new();
+                let (params, rsort) = self.ctx.get_decl(n.as_str()).expect("definition must have a declaration");
                 match self.ctx.get_body(n) {
                     Some(body) => {
-                        ctx.update_logic(self.ctx.get_logic());
+                        let mut ctx = self.ctx.fresh();
                         for (n, s) in params {
-                            ctx.add_decl(n.as_str(), vec![], *s);
+                            ctx.add_decl(n.as_str(), vec![], s.clone());
                         }
                         rsort == &ctx.check_sort(body).expect("body must be well formed")
                     }
@@ -389,46 +958,46 @@ impl Query {
 
 impl Query {
 
-    pub fn mk_nonterminal(&self, name: &str, sort: &str) -> Rc<Term> {
+    pub fn mk_nonterminal(&self, name: &str, sort: &str) -> Arc<Term> {
         Term::new(Symbol::NonTerm(Sort::new(sort), name.to_owned()), vec![])
     }
 
-    pub fn mk_const(&self, name: &str) -> Rc<Term> {
+    pub fn mk_const(&self, name: &str) -> Arc<Term> {
         Term::new(Symbol::new(name), vec! [])
     }
 
-    pub fn mk_app(&self, name: &str, args: Vec<Rc<Term>>) -> Rc<Term> {
-        let rcargs = args.into_iter().map(|a| Rc::clone(&a)).collect();
+    pub fn mk_app(&self, name: &str, args: Vec<Arc<Term>>) -> Arc<Term> {
+        let rcargs = args.into_iter().map(|a| Arc::clone(&a)).collect();
         Term::new(Symbol::new(name), rcargs)
     }
 
     #[allow(dead_code)]
-    pub fn mk_add(&self, x: Rc<Term>, y: Rc<Term>) -> Rc<Term> {
+    pub fn mk_add(&self, x: Arc<Term>, y: Arc<Term>) -> Arc<Term> {
         self.mk_app("+", vec![x, y])
     }
 
     #[allow(dead_code)]
-    pub fn mk_sub(&self, x: Rc<Term>, y: Rc<Term>) -> Rc<Term> {
+    pub fn mk_sub(&self, x: Arc<Term>, y: Arc<Term>) -> Arc<Term> {
         self.mk_app("-", vec![x, y])
     }
 
     #[allow(dead_code)]
-    pub fn mk_neg(&self, x: Rc<Term>) -> Rc<Term> {
+    pub fn mk_neg(&self, x: Arc<Term>) -> Arc<Term> {
         self.mk_app("-", vec![x])
     }
 
     #[allow(dead_code)]
-    pub fn mk_ge(&self, x: Rc<Term>, y: Rc<Term>) -> Rc<Term> {
+    pub fn mk_ge(&self, x: Arc<Term>, y: Arc<Term>) -> Arc<Term> {
         self.mk_app(">=", vec![x, y])
     }
 
     #[allow(dead_code)]
-    pub fn mk_le(&self, x: Rc<Term>, y: Rc<Term>) -> Rc<Term> {
+    pub fn mk_le(&self, x: Arc<Term>, y: Arc<Term>) -> Arc<Term> {
         self.mk_app("<=", vec![x, y])
     }
 
     #[allow(dead_code)]
-    pub fn mk_ite(&self, x: Rc<Term>, y: Rc<Term>, z: Rc<Term>) -> Rc<Term> {
+    pub fn mk_ite(&self, x: Arc<Term>, y: Arc<Term>, z: Arc<Term>) -> Arc<Term> {
         self.mk_app("ite", vec![x, y, z])
     }    
 }
@@ -436,7 +1005,7 @@ impl Query {
 
 #[cfg(test)]
 mod test {
-    use std::rc::Rc;
+    use std::sync::Arc;
     use crate::qry::Query;
 
     #[test]
@@ -447,8 +1016,8 @@ mod test {
         q.declare_fun("f", vec! ["Int", "Int"], "Int");
         let node_x = q.mk_const("x");
         let node_7 = q.mk_const("7");
-        let node_ge = q.mk_ge(Rc::clone(&node_x), Rc::clone(&node_7));
-        let node_f = q.mk_app("f", vec! [Rc::clone(&node_x), Rc::clone(&node_x)]);
+        let node_ge = q.mk_ge(Arc::clone(&node_x), Arc::clone(&node_7));
+        let node_f = q.mk_app("f", vec! [Arc::clone(&node_x), Arc::clone(&node_x)]);
         let node_neg = q.mk_neg(node_f);
         let node_add = q.mk_add(node_neg, node_7);
         let node_le = q.mk_le(q.mk_sub(node_x, q.mk_const("33")), node_add);
@@ -479,6 +1048,20 @@ mod test {
         assert_eq!(unparsed_file, format!("{}", q));
     }
 
+    #[test]
+    fn test_parse_query_rec() {
+        use std::fs;
+        let unparsed_file = fs::read_to_string("tests/data/uflia_rec.smt2").expect("cannot read file");
+        let mut q = Query::new();
+        q.parse_query(&unparsed_file).unwrap();
+        assert_eq!(unparsed_file, format!("{}", q));
+        assert!(q.well_formed());
+
+        // a body of the wrong sort defines nothing
+        assert!(q.parse_query("(define-fun-rec g ((n Int)) Bool (+ n 1))").is_err());
+        assert!(q.peek_ctx().get_decl("g").is_none());
+    }
+
     #[test]
     fn test_parse_answer() {
         use std::fs;
@@ -493,6 +1076,75 @@ mod test {
         assert_eq!("(- 1)", format!("{}", f_term));
     }
 
+    #[test]
+    fn test_parse_solution() {
+        use std::fs;
+        let unparsed_file = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut q = Query::new();
+        q.parse_query(&unparsed_file).unwrap();
+        // a definition on its own, with its own parameter names
+        let sol = q.parse_answer("(define-fun h ((a Int) (b Int)) Bool (<= a b))").unwrap();
+        assert_eq!("(<= x y)", format!("{}", sol["h"]));
+        let sol = q.parse_answer("(define-fun-rec h ((a Int) (b Int)) Bool (or (>= a b) (h (+ a 1) b)))").unwrap();
+        assert_eq!("(or (>= x y) (h (+ x 1) y))", format!("{}", sol["h"]));
+    }
+
+    #[test]
+    fn test_parse_query_array() {
+        use std::fs;
+        let unparsed_file = fs::read_to_string("tests/data/qfauflia.smt2").expect("cannot read file");
+        let mut q = Query::new();
+        q.parse_query(&unparsed_file).unwrap();
+        assert_eq!(unparsed_file, format!("{}", q));
+        assert!(q.well_formed());
+    }
+
+    #[test]
+    fn test_parse_answer_lambda() {
+        use std::fs;
+        let unparsed_file = fs::read_to_string("tests/data/qfauflia.smt2").expect("cannot read file");
+        let mut q = Query::new();
+        q.parse_query(&unparsed_file).unwrap();
+        let unparsed_file = fs::read_to_string("tests/data/qfauflia_lambda_result.smt2").expect("cannot read file");
+        let sol = q.parse_answer(&unparsed_file).unwrap();
+        let a_term = sol.get("a").expect("couldn't find a");
+        assert_eq!("(store (store ((as const (Array Int Int)) 0) 2 4) 1 3)", format!("{}", a_term));
+    }
+
+    #[test]
+    fn test_parse_query_datatype() {
+        use std::fs;
+        let unparsed_file = fs::read_to_string("tests/data/qfdt.smt2").expect("cannot read file");
+        let mut q = Query::new();
+        q.parse_query(&unparsed_file).unwrap();
+        assert_eq!(unparsed_file, format!("{}", q));
+        assert!(q.well_formed());
+    }
+
+    #[test]
+    fn test_parse_query_datatypes() {
+        let mut q = Query::new();
+        q.parse_query("(set-logic QF_UFDTLIA)
+(declare-datatypes ((Tree 0) (Forest 0)) (((node (label Int) (children Forest))) ((nil) (cons (head Tree) (tail Forest)))))
+(declare-const t Tree)
+(assert ((_ is cons) (children t)))").unwrap();
+        assert_eq!("(set-logic QF_UFDTLIA)
+(declare-datatypes ((Tree 0) (Forest 0)) (((node (label Int) (children Forest))) ((nil) (cons (head Tree) (tail Forest)))))
+(declare-const t Tree)
+(assert (is-cons (children t)))", format!("{}", q));
+        assert!(q.well_formed());
+    }
+
+    #[test]
+    fn test_parse_query_string() {
+        use std::fs;
+        let unparsed_file = fs::read_to_string("tests/data/qfslia.smt2").expect("cannot read file");
+        let mut q = Query::new();
+        q.parse_query(&unparsed_file).unwrap();
+        assert_eq!(unparsed_file, format!("{}", q));
+        assert!(q.well_formed());
+    }
+
     #[test]
     fn test_multiple_asserts_lia(){
         let mut q = Query::new();
@@ -500,8 +1152,8 @@ mod test {
         q.declare_fun("x", vec! [], "Int");
         let node_x = q.mk_const("x");
         let node_7 = q.mk_const("7");
-        let a1 = q.mk_ge(Rc::clone(&node_x), Rc::clone(&node_7));
-        let a2 = q.mk_le(Rc::clone(&node_x), Rc::clone(&node_7));
+        let a1 = q.mk_ge(Arc::clone(&node_x), Arc::clone(&node_7));
+        let a2 = q.mk_le(Arc::clone(&node_x), Arc::clone(&node_7));
         q.assert(a1);
         q.assert(a2);
         q.check_sat();
@@ -520,8 +1172,8 @@ mod test {
         q.set_logic("QF_UFLIA");
         q.declare_fun("f", vec! ["Int", "Int"], "Bool");
         let node_n1 = q.mk_const("1");
-        let node_sub = q.mk_neg(Rc::clone(&node_n1));
-        let a1 = q.mk_app("f", vec! [node_sub, Rc::clone(&node_n1)]);
+        let node_sub = q.mk_neg(Arc::clone(&node_n1));
+        let a1 = q.mk_app("f", vec! [node_sub, Arc::clone(&node_n1)]);
         q.assert(a1);
         q.check_sat();
         q.get_model();
@@ -554,10 +1206,95 @@ mod test {
         q.set_logic("QF_UFLIA");
         q.declare_fun("f", vec! ["Int", "Int"], "Bool");
         let node_n1 = q.mk_const("1");
-        let node_sub = q.mk_sub(Rc::clone(&node_n1), Rc::clone(&node_n1));
+        let node_sub = q.mk_sub(Arc::clone(&node_n1), Arc::clone(&node_n1));
         let a1 = q.mk_app("f", vec! [node_sub, node_n1]);
         q.assert(a1);
         println!("{}", q.well_formed());
         assert!(q.well_formed())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_polymorphic_sorts() {
+        use crate::ctx::Sort;
+        let mut q = Query::new();
+        q.set_logic("QF_ALIA");
+        q.declare_const("a", "(Array Int Bool)");
+        q.declare_const("p", "Bool");
+        q.declare_const("x", "Int");
+        let (a, p, x) = (q.mk_const("a"), q.mk_const("p"), q.mk_const("x"));
+        let ctx = q.peek_ctx();
+
+        // variadic, chainable and pairwise operators take any number of arguments
+        let and = q.mk_app("and", vec! [Arc::clone(&p); 6]);
+        assert!(ctx.check_sort(&and) == Some(Sort::Bool));
+        let lt = q.mk_app("<", vec! [Arc::clone(&x); 4]);
+        assert!(ctx.check_sort(&lt) == Some(Sort::Bool));
+        let distinct = q.mk_app("distinct", vec! [Arc::clone(&x); 3]);
+        assert!(ctx.check_sort(&distinct) == Some(Sort::Bool));
+        let neg = q.mk_app("-", vec! [Arc::clone(&x)]);
+        assert!(ctx.check_sort(&neg) == Some(Sort::Int));
+
+        // sort-parametric operators need consistent arguments
+        let sel = q.mk_app("select", vec! [Arc::clone(&a), Arc::clone(&x)]);
+        assert!(ctx.check_sort(&sel) == Some(Sort::Bool));
+        let ite = q.mk_app("ite", vec! [Arc::clone(&sel), Arc::clone(&a), Arc::clone(&a)]);
+        assert!(ctx.check_sort(&ite) == Some(Sort::new("(Array Int Bool)")));
+        let eq = q.mk_app("=", vec! [Arc::clone(&p), Arc::clone(&sel), Arc::clone(&x)]);
+        assert!(ctx.check_sort(&eq).is_none());
+        let bad = q.mk_app("select", vec! [Arc::clone(&a), Arc::clone(&p)]);
+        assert!(ctx.check_sort(&bad).is_none());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/incremental.synth").expect("cannot read file");
+        let mut query = Query::new();
+        let mut problems = vec! [];
+        query.parse_incremental(&unparsed_query, |q| {
+            assert!(q.well_formed());
+            let name = q.get_synth().expect("must have function to synthesize");
+            let (params, _) = q.peek_ctx().get_decl(&name).expect("declaration not found!");
+            problems.push((params.len(), format!("{}", q)));
+        }).expect("cannot parse file");
+
+        // f is declared twice, each time in its own scope
+        assert!(problems.len() == 2);
+        assert!(problems[0].0 == 1 && problems[1].0 == 2);
+        assert!(!problems[1].1.contains("(+ x 1)"));
+        assert!(problems[1].1.contains("(declare-const y Int)"));
+
+        // only what was declared outside every scope survives
+        assert!(query.peek_ctx().get_decl("x").is_some());
+        assert!(query.peek_ctx().get_decl("y").is_none());
+        assert!(query.get_synth().is_none());
+        assert!(format!("{}", query) == "(set-logic QF_UFLIA)\n(declare-const x Int)");
+
+        // there is nothing left to pop
+        assert!(query.parse_incremental("(pop)", |_| ()).is_err());
+        assert!(query.parse_incremental("(push 99999999999999999999999)", |_| ()).is_err());
+    }
+
+    #[test]
+    fn test_parse_counterexamples() {
+        use std::fs;
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        let unparsed_cexs = fs::read_to_string("tests/data/simple_cex.smt2").expect("cannot read file");
+        let cexs = query.parse_counterexamples(&unparsed_cexs).expect("cannot parse counterexamples");
+        assert!(cexs.len() == 3);
+        // what is not declared, or not declared like that, is left out
+        assert!(cexs[1].len() == 4 && !cexs[1].contains_key("old_c"));
+        assert!(cexs[2].len() == 1);
+        // and what is written can be read back
+        let printed = query.model_to_string(&cexs[0]);
+        assert!(printed == query.model_to_string(&cexs[1]));
+        let reread = query.parse_counterexamples(&printed).expect("cannot parse printed model");
+        assert!(reread.len() == 1 && query.model_to_string(&reread[0]) == printed);
+
+        let hints = query.parse_terms("(>= x y)\ntrue\n(+ 1 x)").expect("cannot parse terms");
+        assert!(hints.iter().map(|h| h.to_string()).collect::<Vec<String>>() == vec!["(>= x y)", "true", "(+ 1 x)"]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Lines, Write};
+use std::time::Duration;
+
+use pest::error::{Error, ErrorVariant, LineColLocation};
+
+use crate::qry::{Query, Rule};
+use crate::smt::Backend;
+use crate::syn::{Limit, Outcome, Strategy};
+
+// an interactive session: commands are read one at a time, like an interactive SMT solver reads
+// them, and change a live query. Besides the commands of a script there are
+// (check-synth)              solve the problem as it is now, which (check-sat) also does
+// (get-counterexamples)      the counterexamples the last check finished with
+// (set-option :name value)   the options of the command line, e.g. (set-option :timeout 10)
+// (reset)                    start over, keeping the options
+// (exit)
+// A command that goes wrong is answered with (error "...") and leaves the query as it was
+pub fn run<R: BufRead, W: Write>(mut query: Query, input: R, mut output: W) {
+    for command in Commands::new(input) {
+        if tokens(&command).first().map(|t| t.as_str()) == Some("exit") {
+            break
+        }
+        let answer = match step(&query, &command) {
+            Ok((next, answer)) => {
+                query = next;
+                answer
+            },
+            Err(e) => Some(error(&e)),
+        };
+        if let Some(answer) = answer {
+            writeln!(output, "{}", answer).expect("cannot write answer");
+            output.flush().expect("cannot write answer");
+        }
+    }
+}
+
+// the commands in the input, each as soon as its parentheses close, so we can answer it before
+// reading on
+struct Commands<R> {
+    lines: Lines<R>,
+    // those closed on the last line read
+    ready: VecDeque<String>,
+    command: String,
+    depth: usize,
+    quoted: bool,
+}
+
+impl<R: BufRead> Commands<R> {
+    fn new(input: R) -> Commands<R> {
+        Commands { lines: input.lines(), ready: VecDeque::new(), command: String::new(), depth: 0, quoted: false }
+    }
+}
+
+impl<R: BufRead> Iterator for Commands<R> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while self.ready.is_empty() {
+            let line = self.lines.next()?.expect("cannot read input");
+            for c in line.chars() {
+                if !self.quoted && c == ';' {
+                    break
+                }
+                self.command.push(c);
+                match c {
+                    '"' => self.quoted = !self.quoted,
+                    '(' if !self.quoted => self.depth += 1,
+                    // a parenthesis too many closes the command too, so that it gets an error
+                    ')' if !self.quoted && self.depth <= 1 => {
+                        self.depth = 0;
+                        self.ready.push_back(self.command.trim().to_owned());
+                        self.command.clear();
+                    },
+                    ')' if !self.quoted => self.depth -= 1,
+                    _ => ()
+                }
+            }
+            self.command.push('\n');
+        }
+        self.ready.pop_front()
+    }
+}
+
+fn tokens(command: &str) -> Vec<String> {
+    command.replace(['(', ')'], " ").split_whitespace().map(|t| t.to_owned()).collect()
+}
+
+// what the command does to a copy of the query, and what to answer
+fn step(query: &Query, command: &str) -> Result<(Query, Option<String>), String> {
+    let tokens = tokens(command);
+    let mut next = query.clone();
+    match tokens.first().map(|t| t.as_str()) {
+        Some("check-synth") | Some("check-sat") => {
+            let name = query.get_synth().ok_or("there is no function to synthesize")?;
+            let mut problem = query.clone();
+            problem.check_sat();
+            let answer = match problem.solve() {
+                Outcome::Solved(body) => problem.solution_to_string(&name, &body),
+                Outcome::NoSolution => "(no-solution)".to_owned(),
+                Outcome::Exhausted(Limit::Candidates, _)
+                | Outcome::Exhausted(Limit::Iterations, _) => "(budget-exhausted)".to_owned(),
+                Outcome::Exhausted(Limit::Failed(e), _) => error(&format!("solver failed: {}", e)),
+                Outcome::Exhausted(_, _) => "(timeout)".to_owned(),
+            };
+            // the next check starts from where this one got to
+            next.set_counterexamples(problem.get_counterexamples().to_vec());
+            next.set_stats(problem.get_stats().clone());
+            Ok((next, Some(answer)))
+        },
+        Some("get-counterexamples") => {
+            let models: Vec<String> = query.get_counterexamples().iter().map(|c| query.model_to_string(c)).collect();
+            Ok((next, Some(format!("({})", models.join("\n")))))
+        },
+        Some("set-option") => {
+            match (tokens.get(1), tokens.get(2)) {
+                (Some(option), Some(value)) => set_option(&mut next, option, value)?,
+                _ => return Err("set-option needs an option and a value".to_owned())
+            }
+            Ok((next, None))
+        },
+        Some("reset") => {
+            let mut fresh = Query::new();
+            fresh.set_budget(query.get_budget().clone());
+            fresh.set_config(query.get_config());
+            fresh.set_cache(query.get_cache());
+            Ok((fresh, None))
+        },
+        _ => {
+            next.parse_incremental(command, |_| ()).map_err(|e| describe(&e))?;
+            Ok((next, None))
+        }
+    }
+}
+
+fn set_option(query: &mut Query, option: &str, value: &str) -> Result<(), String> {
+    let mut budget = query.get_budget().clone();
+    let mut config = query.get_config();
+    let seconds = || value.parse().map(Duration::from_secs_f64).map_err(|_| format!("{} takes a number of seconds", option));
+    let count = || value.parse().map_err(|_| format!("{} takes a number", option));
+    let flag = || value.parse().map_err(|_| format!("{} takes true or false", option));
+    match option {
+        ":timeout" => budget.timeout = Some(seconds()?),
+        ":solver-timeout" => budget.solver_timeout = Some(seconds()?),
+        ":max-candidates" => budget.max_candidates = Some(count()?),
+        ":max-iterations" => budget.max_iterations = Some(count()?),
+        ":jobs" => budget.jobs = count()?,
+        ":threads" => budget.threads = count()?,
+        ":strategy" => config.strategy = Strategy::parse(value).ok_or(format!("unknown strategy {}", value))?,
+        ":solver" => config.backend = Backend::parse(value).ok_or(format!("unknown solver {}", value))?,
+        ":prune" => config.prune = flag()?,
+        ":symbolic-constants" => config.symbolic = flag()?,
+        ":recursive" => config.recursive = flag()?,
+        _ => return Err(format!("unsupported option {}", option))
+    }
+    query.set_budget(budget);
+    query.set_config(config);
+    Ok(())
+}
+
+// what is wrong with a command, on one line
+fn describe(e: &Error<Rule>) -> String {
+    let ((line, column), _) = match e.line_col {
+        LineColLocation::Pos(p) => (p, p),
+        LineColLocation::Span(p, q) => (p, q),
+    };
+    match &e.variant {
+        ErrorVariant::CustomError { message } => message.clone(),
+        ErrorVariant::ParsingError { .. } => format!("syntax error at line {}, column {}", line, column),
+    }
+}
+
+// errors the way SMT-LIB solvers give them
+fn error(message: &str) -> String {
+    format!("(error \"{}\")", message.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::qry::Query;
+    use super::run;
+
+    fn session(input: &str) -> Vec<String> {
+        let mut output = vec![];
+        run(Query::new(), input.as_bytes(), &mut output);
+        String::from_utf8(output).expect("answers are text").lines().map(|l| l.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_session() {
+        // commands can span lines, and mistakes leave the query as it was
+        let answers = session("(set-logic QF_LIA)\n(synth-blocking-fun f ((x Int)) Bool)\n(declare-const x Int)\n(declare-const x Int)\n(assert (not\n  (= (f x) (>= x 3)))) ; the spec\n(set-option :max-iterations 0)\n(check-synth)\n(get-counterexamples)\n(set-option :bogus 1)");
+        assert!(answers.len() == 4);
+        assert!(answers[0] == "(error \"x is already declared\")");
+        assert!(answers[1] == "(budget-exhausted)");
+        assert!(answers[2] == "()");
+        assert!(answers[3] == "(error \"unsupported option :bogus\")");
+    }
+
+    #[test]
+    fn test_push_pop_reset() {
+        // what is popped or reset is gone, and the options stay
+        let answers = session("(set-logic QF_LIA)\n(set-option :max-iterations 0)\n(push)\n(synth-blocking-fun f ((x Int)) Bool)\n(pop)\n(check-synth)\n(push 1)\n(synth-blocking-fun f ((x Int)) Bool)\n(declare-const x Int)\n(assert (f x))\n(check-synth)\n(reset)\n(check-synth)\n(exit)\n(check-synth)");
+        assert!(answers == ["(error \"there is no function to synthesize\")", "(budget-exhausted)", "(error \"there is no function to synthesize\")"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let answers = session("(declare-const x Int)\n(set-logic QF_LIA)\n(pop)\n(set-option :strategy sideways)\n(assert (+ 1 2))\n(declare-const x Int))\n(declare-const x Int)\n(assert (> x 0)");
+        assert!(answers == [
+            "(error \"sort Int not supported by the logic\")",
+            "(error \"pop without matching push\")",
+            "(error \"unknown strategy sideways\")",
+            "(error \"assertion must be a well-sorted Bool\")",
+            "(error \"syntax error at line 1, column 1\")",
+            "(error \"x is already declared\")",
+        ]);
+
+        // nor do commands the logic or the symbols declared so far rule out
+        let answers = session("(set-logic QF_BV)\n(set-logic QF_LIA)\n(declare-fun f (Int) Int)\n(declare-const y (BitVec 8))\n(declare-datatype T ((a)))\n(set-logic QF_UFDTLIA)\n(declare-datatype T ((a) (b)))\n(declare-datatype T ((c)))\n(declare-datatype U ((a)))\n(declare-datatype U ((u (s Int)) (v (s Int))))\n(declare-datatypes ((V 0) (W 0)) (((v))))");
+        assert!(answers == [
+            "(error \"logic QF_BV not supported\")",
+            "(error \"functions with parameters not supported by the logic\")",
+            "(error \"sort (BitVec 8) not supported by the logic\")",
+            "(error \"datatypes not supported by the logic\")",
+            "(error \"T is already declared\")",
+            "(error \"a is already declared\")",
+            "(error \"s is already declared\")",
+            "(error \"need exactly one declaration per datatype\")",
+        ]);
+    }
+
+    #[test]
+    fn test_answers_before_reading_on() {
+        use std::io::{self, BufReader, Read};
+
+        // reading past (exit) fails the test
+        struct Closed;
+        impl Read for Closed {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                panic!("read past (exit)")
+            }
+        }
+        let input = BufReader::new("(set-option :bogus 1)\n(exit)\n".as_bytes().chain(Closed));
+        let mut output = vec![];
+        run(Query::new(), input, &mut output);
+        assert!(output == b"(error \"unsupported option :bogus\")\n");
+    }
+}
@@ -1,9 +1,10 @@
 use std::collections::{HashMap};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::ast::{Term, Symbol};
+use crate::ctx::{Context, Solution, Sort};
 
-pub fn rename(rules: &HashMap<String, String>, t: &Rc<Term>) -> Rc<Term> {
+pub fn rename(rules: &HashMap<String, String>, t: &Arc<Term>) -> Arc<Term> {
     let args = t.get_args().map(|a| rename(&rules, a)).collect();
     let symbol = match t.get_symbol() {
         Symbol::Func(n) => if rules.contains_key(n) {
@@ -11,10 +12,255 @@ pub fn rename(rules: &HashMap<String, String>, t: &Rc<Term>) -> Rc<Term> {
         } else {
             Symbol::Func(n.clone())
         }
-        Symbol::BoolLit(b) => Symbol::BoolLit(*b),
-        Symbol::IntLit(b) => Symbol::IntLit(*b),
-        Symbol::NonTerm(s, n) => Symbol::NonTerm(*s, n.clone()),
-
+        other => other.clone(),
     };
     Term::mk_app(symbol, args)
-}
\ No newline at end of file
+}
+
+// replace the constants with values for them, like those in a model
+pub fn substitute(values: &Solution, t: &Arc<Term>) -> Arc<Term> {
+    match t.get_symbol() {
+        Symbol::Func(n) if t.get_args().len() == 0 && values.contains_key(n) => Arc::clone(&values[n]),
+        _ => Term::new(t.get_symbol().clone(), t.get_args().map(|a| substitute(values, a)).collect()),
+    }
+}
+
+// replace the calls to a function with its body, the parameters replaced with the arguments
+pub fn inline(name: &str, params: &[(String, Sort)], body: &Arc<Term>, t: &Arc<Term>) -> Arc<Term> {
+    let args: Vec<Arc<Term>> = t.get_args().map(|a| inline(name, params, body, a)).collect();
+    match t.get_symbol() {
+        Symbol::Func(n) if n == name => {
+            let values = params.iter().map(|(p, _)| p.clone()).zip(args).collect();
+            substitute(&values, body)
+        }
+        _ => Term::new(t.get_symbol().clone(), args),
+    }
+}
+
+// turn (lambda ((x I)) (ite (= x k1) v1 (ite (= x k2) v2 d))) into
+// (store (store ((as const (Array I E)) d) k2 v2) k1 v1)
+pub fn lambda_to_store(asort: &Sort, param: &str, body: &Arc<Term>) -> Option<Arc<Term>> {
+    if !mentions(param, body) {
+        return Some(Term::mk_app(Symbol::ConstArray(asort.clone()), vec![Arc::clone(body)]))
+    }
+    match body.get_symbol() {
+        Symbol::Func(n) if n == "ite" => {
+            let args: Vec<&Arc<Term>> = body.get_args().collect();
+            let index = point(param, args[0])?;
+            if mentions(param, args[1]) {
+                return None
+            }
+            let rest = lambda_to_store(asort, param, args[2])?;
+            Some(Term::mk_app(Symbol::new("store"), vec![rest, index, Arc::clone(args[1])]))
+        }
+        _ => None
+    }
+}
+
+// an equivalent term that is no bigger: constants folded, identities applied and the operands of
+// commutative operators in order, so that terms that only differ in those ways come out the same
+pub fn simplify(ctx: &Context, t: &Arc<Term>) -> Arc<Term> {
+    if t.get_args().len() == 0 {
+        return Arc::clone(t)
+    }
+    let args: Vec<Arc<Term>> = t.get_args().map(|a| simplify(ctx, a)).collect();
+    match t.get_symbol() {
+        Symbol::Func(name) => rewrite(ctx, name, args),
+        other => Term::mk_app(other.clone(), args),
+    }
+}
+
+// apply name to arguments that are already simple
+fn rewrite(ctx: &Context, name: &str, mut args: Vec<Arc<Term>>) -> Arc<Term> {
+    // builtins on values are values, unless SMT-LIB leaves them unspecified
+    if ctx.get_builtin(name).is_some() && args.iter().all(is_value) {
+        match ctx.eval(&Solution::new(), &Term::mk_app(Symbol::new(name), args.clone())) {
+            Symbol::NonTerm(_, _) => (),
+            v => return Term::mk_const(v)
+        }
+    }
+    let lit = |l: &str| Term::mk_const(Symbol::new(l));
+    match (name, args.len()) {
+        ("+", _) | ("*", _) | ("and", _) | ("or", _) => {
+            // (+ (+ a b) c) is (+ a b c)
+            args = args.into_iter().flat_map(|a| match a.get_symbol() {
+                Symbol::Func(n) if n == name => a.get_args().cloned().collect(),
+                _ => vec![a]
+            }).collect();
+            let (unit, zero) = match name {
+                "+" => (lit("0"), None),
+                "*" => (lit("1"), Some(lit("0"))),
+                "and" => (lit("true"), Some(lit("false"))),
+                _ => (lit("false"), Some(lit("true"))),
+            };
+            if zero.as_ref().is_some_and(|z| args.contains(z)) {
+                return zero.expect("unreachable")
+            }
+            args.retain(|a| a != &unit);
+            args.sort();
+            // x and x is x, but x + x is not
+            if name == "and" || name == "or" {
+                args.dedup();
+                if args.iter().any(|a| args.contains(&negate(a))) {
+                    return if name == "and" {lit("false")} else {lit("true")}
+                }
+            }
+            // fold what is constant together, the values sort first
+            let values = args.iter().take_while(|a| is_value(a)).count();
+            if values > 1 {
+                let rest = args.split_off(values);
+                let folded = rewrite(ctx, name, args);
+                args = std::iter::once(folded).chain(rest).collect();
+                args.retain(|a| a != &unit);
+            }
+            match args.len() {
+                0 => unit,
+                1 => args.pop().expect("unreachable"),
+                _ => Term::mk_app(Symbol::new(name), args)
+            }
+        }
+        ("-", 1) => match args[0].get_symbol() {
+            Symbol::Func(n) if n == "-" && args[0].get_args().len() == 1 => Arc::clone(args[0].get_args().next().expect("unreachable")),
+            _ => Term::mk_app(Symbol::new(name), args)
+        },
+        ("-", 2) if args[0] == args[1] => lit("0"),
+        ("-", 2) if args[1] == lit("0") => args.swap_remove(0),
+        ("not", 1) => negate(&args[0]),
+        ("=>", 2) => {
+            let b = args.pop().expect("unreachable");
+            let a = args.pop().expect("unreachable");
+            if a == lit("true") {
+                b
+            } else if a == lit("false") || b == lit("true") || a == b {
+                lit("true")
+            } else if b == lit("false") {
+                negate(&a)
+            } else {
+                Term::mk_app(Symbol::new(name), vec![a, b])
+            }
+        }
+        ("=", 2) | ("distinct", 2) | ("xor", 2) => {
+            args.sort();
+            if args[0] == args[1] {
+                return lit(if name == "=" {"true"} else {"false"})
+            }
+            match (name, args[0].get_symbol()) {
+                // the values sort first
+                ("xor", Symbol::BoolLit(false)) | ("=", Symbol::BoolLit(true)) => args.swap_remove(1),
+                ("xor", Symbol::BoolLit(true)) | ("=", Symbol::BoolLit(false)) => negate(&args[1]),
+                _ => Term::mk_app(Symbol::new(name), args)
+            }
+        }
+        ("=", _) | ("distinct", _) => {
+            args.sort();
+            Term::mk_app(Symbol::new(name), args)
+        }
+        ("<=", 2) | (">=", 2) if args[0] == args[1] => lit("true"),
+        ("<", 2) | (">", 2) if args[0] == args[1] => lit("false"),
+        ("ite", 3) => {
+            let b = args.pop().expect("unreachable");
+            let a = args.pop().expect("unreachable");
+            let c = args.pop().expect("unreachable");
+            if c == lit("true") || a == b {
+                a
+            } else if c == lit("false") {
+                b
+            } else if a == lit("true") && b == lit("false") {
+                c
+            } else if a == lit("false") && b == lit("true") {
+                negate(&c)
+            } else {
+                match c.get_symbol() {
+                    Symbol::Func(n) if n == "not" => {
+                        let c = Arc::clone(c.get_args().next().expect("not must have an argument"));
+                        Term::mk_app(Symbol::new(name), vec![c, b, a])
+                    }
+                    _ => Term::mk_app(Symbol::new(name), vec![c, a, b])
+                }
+            }
+        }
+        _ => Term::mk_app(Symbol::new(name), args)
+    }
+}
+
+// (not t), simplified
+fn negate(t: &Arc<Term>) -> Arc<Term> {
+    match t.get_symbol() {
+        Symbol::BoolLit(b) => Term::mk_const(Symbol::BoolLit(!b)),
+        Symbol::Func(n) if n == "not" => Arc::clone(t.get_args().next().expect("not must have an argument")),
+        _ => Term::mk_app(Symbol::new("not"), vec![Arc::clone(t)])
+    }
+}
+
+fn is_value(t: &Arc<Term>) -> bool {
+    t.get_args().len() == 0 && matches!(t.get_symbol(),
+        Symbol::BoolLit(_) | Symbol::IntLit(_) | Symbol::StrLit(_) | Symbol::ArrayLit(_, _, _) | Symbol::DtLit(_, _, _))
+}
+
+// if cond is (= param k) or (= k param) then return k
+fn point(param: &str, cond: &Arc<Term>) -> Option<Arc<Term>> {
+    let args: Vec<&Arc<Term>> = cond.get_args().collect();
+    match cond.get_symbol() {
+        Symbol::Func(n) if n == "=" && args.len() == 2 => {
+            if is_var(param, args[0]) && !mentions(param, args[1]) {
+                Some(Arc::clone(args[1]))
+            } else if is_var(param, args[1]) && !mentions(param, args[0]) {
+                Some(Arc::clone(args[0]))
+            } else {
+                None
+            }
+        }
+        _ => None
+    }
+}
+
+fn is_var(name: &str, t: &Arc<Term>) -> bool {
+    t.get_args().len() == 0 && t.get_symbol() == &Symbol::Func(name.to_owned())
+}
+
+fn mentions(name: &str, t: &Arc<Term>) -> bool {
+    is_var(name, t) || t.get_args().any(|a| mentions(name, a))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::qry::Query;
+    use super::simplify;
+
+    #[test]
+    fn test_simplify() {
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+
+        let cases = [
+            ("(+ 0 x)", "x"),
+            ("(+ x (+ 1 (+ y 2)))", "(+ 3 x y)"),
+            ("(* 0 x)", "0"),
+            ("(- x x)", "0"),
+            ("(- (- x))", "x"),
+            ("(+ 2 (* 3 4))", "14"),
+            ("(ite true x y)", "x"),
+            ("(ite (>= x y) x x)", "x"),
+            ("(ite (not (>= x y)) true false)", "(not (>= x y))"),
+            ("(ite (not (>= x y)) x y)", "(ite (>= x y) y x)"),
+            ("(and (>= x y) true (>= x y))", "(>= x y)"),
+            ("(or (>= x y) (not (>= x y)))", "true"),
+            ("(not (not (<= x y)))", "(<= x y)"),
+            ("(=> (= x x) (< y y))", "false"),
+            ("(= false (<= x y))", "(not (<= x y))"),
+            ("(= y x)", "(= x y)"),
+            ("(+ y x)", "(+ x y)"),
+            ("(- 3 10)", "(- 7)"),
+        ];
+        let terms = query.parse_terms(&cases.iter().map(|(t, _)| *t).collect::<Vec<&str>>().join("\n")).expect("cannot parse terms");
+        for (t, (_, expected)) in terms.iter().zip(cases.iter()) {
+            let simple = simplify(query.peek_ctx(), t);
+            assert!(&simple.to_string() == expected, "{} simplified to {}", t, simple);
+            // and there is nothing left to do
+            assert!(simplify(query.peek_ctx(), &simple) == simple);
+        }
+    }
+}
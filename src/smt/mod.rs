@@ -1,58 +1,384 @@
-use std::process::Command;
-use std::io::Write;
-use std::fs::File;
+use std::process::{self, Command, Stdio};
+use std::io::{Read, Write};
+use std::fs;
 use std::env;
+use std::path::PathBuf;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crate::qry::{Query};
 use crate::ctx::Solution;
+use crate::trc::Replay;
 
-impl Query {
-    fn write_to_tmp(&self) -> String {
-        debug!("writing {}", self);
-        let mut dir = env::temp_dir();
-        dir.push("tmp.smt2");
+#[derive(Debug)]
+pub enum SolverError {
+    // the solver did not answer in time and was killed
+    Timeout,
+    // nobody needs the answer anymore
+    Cancelled,
+    // the solver ran but exited with a non-zero status (none if it was killed by a signal) and said why
+    Exit(Option<i32>, String),
+    Failed(String),
+}
 
-        let path_name = format!("{}", dir.to_string_lossy());
+impl fmt::Display for SolverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolverError::Timeout => write!(f, "solver timed out"),
+            SolverError::Cancelled => write!(f, "solver call cancelled"),
+            SolverError::Exit(Some(code), e) => write!(f, "solver exited with status {}: {}", code, e),
+            SolverError::Exit(None, e) => write!(f, "solver was killed: {}", e),
+            SolverError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// run a solver on input, fed through a pipe so no other process can see or clobber it, and collect
+// its stdout, killing it if it takes longer than timeout or is cancelled
+fn run(mut cmd: Command, input: &str, timeout: Option<Duration>, cancel: Option<&AtomicBool>) -> Result<String, SolverError> {
+    debug!("writing {}", input);
+    let mut child = match cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(SolverError::Failed(format!("cannot run {}: {}", cmd.get_program().to_string_lossy(), e)))
+    };
 
-        let mut file = File::create(dir).expect("failed to create tmp file");
-        file.write_all(format!("{}", self).as_bytes()).expect("failed to write to tmp file");
+    // write and read on other threads so a large query or a chatty solver can't fill a pipe and block.
+    // A solver that stops reading early closes its end, which is not our problem
+    let mut stdin = child.stdin.take().expect("stdin must be piped");
+    let text = input.to_owned();
+    let writer = thread::spawn(move || {
+        stdin.write_all(text.as_bytes()).ok();
+    });
+    let stdout = read_all(child.stdout.take().expect("stdout must be piped"));
+    let stderr = read_all(child.stderr.take().expect("stderr must be piped"));
 
-        path_name
+    let status = if timeout.is_some() || cancel.is_some() {
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait().expect("failed to wait on solver") {
+                break status
+            }
+            let timed_out = timeout.is_some_and(|limit| start.elapsed() >= limit);
+            let cancelled = cancel.is_some_and(|c| c.load(Ordering::SeqCst));
+            if timed_out || cancelled {
+                child.kill().expect("failed to kill solver");
+                child.wait().expect("failed to wait on solver");
+                return Err(if timed_out {SolverError::Timeout} else {SolverError::Cancelled})
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    } else {
+        child.wait().expect("failed to wait on solver")
+    };
+
+    writer.join().expect("failed to write stdin");
+    let out = stdout.join().expect("failed to read stdout");
+    let err = stderr.join().expect("failed to read stderr");
+    if !status.success() {
+        // solvers don't agree on where errors go
+        let why = if err.trim().is_empty() {out.trim()} else {err.trim()};
+        return Err(SolverError::Exit(status.code(), why.to_owned()))
+    }
+    if !err.trim().is_empty() {
+        debug!("solver stderr: {}", err.trim());
     }
+    Ok(out)
+}
 
-    pub fn check_cvc4(&self) -> Result<Solution, String> {
-        let path_name = self.write_to_tmp();
-        let output = Command::new("cvc4")
-            .arg("--lang")
-            .arg("smt")
-            .arg("--produce-models")
-            .arg("--dump-models")
-            .arg(path_name)
-            .output()
-            .expect("failed to execute process");
+fn read_all<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut out = Vec::new();
+        pipe.read_to_end(&mut out).expect("failed to read from solver");
+        String::from_utf8_lossy(&out).into_owned()
+    })
+}
 
-        let out = std::str::from_utf8(&output.stdout).expect("failed to read stdout");
-        debug!("cvc4 answer: {}", out);
-        match self.parse_answer(out) {
-            Ok(s) => Ok(s),
-            Err(e) => Err(e.to_string())
+// solver answers kept on disk across runs, one file per query, named by a hash of the query.
+// Each file holds the query it answers on the first line, so that a collision is just a miss
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Cache {
+        fs::create_dir_all(&dir).expect("cannot create cache directory");
+        Cache { dir }
+    }
+
+    // $XDG_CACHE_HOME/llama, ~/.cache/llama or somewhere in the temp directory
+    pub fn default_dir() -> PathBuf {
+        let mut dir = match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+            (Some(d), _) => PathBuf::from(d),
+            (None, Some(h)) => PathBuf::from(h).join(".cache"),
+            (None, None) => env::temp_dir(),
+        };
+        dir.push("llama");
+        dir
+    }
+
+    // queries that only differ in whitespace are the same query, unless it is inside a string literal
+    fn key(backend: Backend, text: &str) -> String {
+        let mut key = format!("{} ", backend);
+        let mut quoted = false;
+        let mut space = false;
+        for c in text.trim().chars() {
+            if !quoted && c.is_whitespace() {
+                space = true;
+                continue
+            }
+            if space {
+                key.push(' ');
+                space = false;
+            }
+            // "" inside a literal is an escaped quote, which toggles twice
+            if c == '"' {
+                quoted = !quoted;
+            }
+            // the key is the first line of its entry
+            match c {
+                '\\' => key.push_str("\\\\"),
+                '\n' => key.push_str("\\n"),
+                '\r' => key.push_str("\\r"),
+                _ => key.push(c)
+            }
         }
+        key
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        // fnv-1a, which unlike the standard hasher is the same on every build
+        let hash = key.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+        self.dir.join(format!("{:016x}.answer", hash))
+    }
+
+    pub fn get(&self, backend: Backend, text: &str) -> Option<String> {
+        let key = Cache::key(backend, text);
+        let entry = fs::read_to_string(self.path(&key)).ok()?;
+        let (query, answer) = entry.split_once('\n')?;
+        if query == key {
+            debug!("cache hit: {}", self.path(&key).to_string_lossy());
+            Some(answer.to_owned())
+        } else {
+            None
+        }
+    }
+
+    pub fn put(&self, backend: Backend, text: &str, answer: &str) {
+        // the solver may know next time, e.g. with more time
+        if answer.trim_start().starts_with("unknown") {
+            return
+        }
+        let key = Cache::key(backend, text);
+        let path = self.path(&key);
+        // write somewhere private then move it into place, so that readers never see half an entry
+        let tmp = self.dir.join(format!(".{}-{:?}.tmp", process::id(), thread::current().id()));
+        let written = fs::write(&tmp, format!("{}\n{}", key, answer)).and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = written {
+            debug!("cannot write to cache: {}", e);
+            fs::remove_file(&tmp).ok();
+        }
+    }
+
+    pub fn clear(&self) {
+        for entry in fs::read_dir(&self.dir).expect("cannot read cache directory") {
+            let path = entry.expect("cannot read cache directory").path();
+            if path.extension().is_some_and(|e| e == "answer" || e == "tmp") {
+                fs::remove_file(path).expect("cannot clear cache");
+            }
+        }
+    }
+}
+
+// what the solver says about text, from the cache if it has been asked before
+fn ask(backend: Backend, text: &str, timeout: Option<Duration>, cancel: Option<&AtomicBool>, cache: Option<&Cache>) -> Result<String, SolverError> {
+    if let Some(answer) = cache.and_then(|c| c.get(backend, text)) {
+        return Ok(answer)
+    }
+    let answer = run(backend.command(), text, timeout, cancel)?;
+    if let Some(c) = cache {
+        c.put(backend, text, &answer);
+    }
+    Ok(answer)
+}
+
+// the solvers we know how to call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Cvc4,
+    Z3,
+}
+
+impl Backend {
+    pub fn new(name: &str) -> Backend {
+        Backend::parse(name).unwrap_or_else(|| panic!("unknown solver {}", name))
+    }
+
+    pub fn parse(name: &str) -> Option<Backend> {
+        match name {
+            "cvc4" => Some(Backend::Cvc4),
+            "z3" => Some(Backend::Z3),
+            _ => None
+        }
+    }
+
+    // the solver reads the query from stdin
+    fn command(&self) -> Command {
+        match self {
+            Backend::Cvc4 => {
+                let mut cmd = Command::new("cvc4");
+                cmd.arg("--lang")
+                    .arg("smt")
+                    .arg("--incremental")
+                    .arg("--produce-models")
+                    .arg("--dump-models")
+                    // find models for recursive definitions
+                    .arg("--fmf-fun");
+                cmd
+            }
+            Backend::Z3 => {
+                let mut cmd = Command::new("z3");
+                cmd.arg("-in")
+                    .arg("dump-models=true");
+                cmd
+            }
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Cvc4 => write!(f, "cvc4"),
+            Backend::Z3 => write!(f, "z3"),
+        }
+    }
+}
+
+impl Query {
+    pub fn check(&self, backend: Backend) -> Result<Solution, SolverError> {
+        let cache = self.get_cache();
+        let out = ask(backend, &self.to_string(), self.get_budget().solver_timeout, None, cache.as_deref())?;
+        self.read_answer(&out)
     }
 
     #[allow(dead_code)]
-    pub fn check_z3(&self) -> Result<Solution, String> {
-        let path_name = self.write_to_tmp();
-        let output = Command::new("z3")
-            .arg(path_name)
-            .arg("dump-models=true")
-            .output()
-            .expect("failed to execute process");
-        
-        let out = std::str::from_utf8(&output.stdout).expect("failed to read stdout");
-        debug!("z3 answer: {}", out);
+    pub fn check_cvc4(&self) -> Result<Solution, SolverError> {
+        self.check(Backend::Cvc4)
+    }
+
+    // turn what a solver printed into a model, empty if there is none
+    pub fn read_answer(&self, out: &str) -> Result<Solution, SolverError> {
+        debug!("solver answer: {}", out);
         match self.parse_answer(out) {
             Ok(s) => Ok(s),
-            Err(e) => Err(e.to_string())
+            Err(e) => Err(SolverError::Failed(e.to_string()))
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn check_z3(&self) -> Result<Solution, SolverError> {
+        self.check(Backend::Z3)
+    }
+}
+
+type Job = (usize, String);
+type Answer = (usize, Result<String, SolverError>);
+
+// a fixed number of solver workers checking queries concurrently, or looking them up in a replay.
+// Only text crosses threads: queries go in, raw solver output comes back
+pub struct Pool {
+    jobs: Option<Sender<Job>>,
+    answers: Receiver<Answer>,
+    cancel: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+    outstanding: usize,
+}
+
+impl Pool {
+    pub fn new(backend: Backend, size: usize, timeout: Option<Duration>, replay: Option<Arc<Replay>>, cache: Option<Arc<Cache>>) -> Pool {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (results, answers) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..std::cmp::max(size, 1)).map(|_| {
+            let queue = Arc::clone(&queue);
+            let results: Sender<Answer> = results.clone();
+            let cancel = Arc::clone(&cancel);
+            let replay = replay.clone();
+            let cache = cache.clone();
+            thread::spawn(move || {
+                loop {
+                    let job = queue.lock().expect("job queue poisoned").recv();
+                    match job {
+                        Ok((id, text)) => {
+                            let answer = if cancel.load(Ordering::SeqCst) {
+                                Err(SolverError::Cancelled)
+                            } else if let Some(replay) = &replay {
+                                replay.answer(&text)
+                            } else {
+                                ask(backend, &text, timeout, Some(&cancel), cache.as_deref())
+                            };
+                            if results.send((id, answer)).is_err() {
+                                break
+                            }
+                        }
+                        // the pool was dropped
+                        Err(_) => break
+                    }
+                }
+            })
+        }).collect();
+
+        Pool { jobs: Some(jobs), answers, cancel, workers, outstanding: 0 }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+
+    pub fn submit(&mut self, id: usize, text: String) {
+        self.jobs.as_ref().expect("pool is shut down").send((id, text)).expect("workers hung up");
+        self.outstanding += 1;
+    }
+
+    // wait for an answer, for at most timeout
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Option<Answer> {
+        assert!(self.outstanding > 0, "nothing to wait for");
+        let answer = match timeout {
+            Some(t) => match self.answers.recv_timeout(t) {
+                Ok(a) => Some(a),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => panic!("workers hung up"),
+            },
+            None => Some(self.answers.recv().expect("workers hung up")),
+        };
+        if answer.is_some() {
+            self.outstanding -= 1;
+        }
+        answer
+    }
+}
+
+impl Drop for Pool {
+    // kill whatever is still running
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        self.jobs = None;
+        for w in self.workers.drain(..) {
+            w.join().expect("worker panicked");
         }
     }
 }
@@ -83,4 +409,87 @@ mod test {
         assert!(q.eval(&sol_z3).unwrap());
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_solver_timeout() {
+        use std::process::Command;
+        use std::time::{Duration, Instant};
+        use super::{run, SolverError};
+
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        let start = Instant::now();
+        match run(cmd, "", Some(Duration::from_millis(100)), None) {
+            Err(SolverError::Timeout) => (),
+            _ => panic!("expected a timeout")
+        }
+        // the child was killed rather than waited on
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_solver_cancel() {
+        use std::process::Command;
+        use std::sync::atomic::AtomicBool;
+        use std::time::{Duration, Instant};
+        use super::{run, SolverError};
+
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        let start = Instant::now();
+        match run(cmd, "", None, Some(&AtomicBool::new(true))) {
+            Err(SolverError::Cancelled) => (),
+            _ => panic!("expected the call to be cancelled")
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_solver_pipes() {
+        use std::process::Command;
+        use super::{run, SolverError};
+
+        // the query goes in on stdin and the answer comes back on stdout
+        let out = run(Command::new("cat"), "(check-sat)", None, None).expect("cat failed");
+        assert!(out == "(check-sat)");
+
+        // a failing solver is reported with what it said on stderr
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("cat > /dev/null; echo oops >&2; exit 3");
+        match run(cmd, "(check-sat)", None, None) {
+            Err(SolverError::Exit(Some(3), e)) => assert!(e == "oops"),
+            _ => panic!("expected a non-zero exit status")
+        }
+
+        match run(Command::new("no-such-solver"), "", None, None) {
+            Err(SolverError::Failed(_)) => (),
+            _ => panic!("expected the solver to be missing")
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        use std::env;
+        use super::{Backend, Cache};
+
+        let cache = Cache::new(env::temp_dir().join(format!("llama-{}-cache", std::process::id())));
+        assert!(cache.get(Backend::Cvc4, "(check-sat)").is_none());
+        cache.put(Backend::Cvc4, "(assert true)\n(check-sat)\n", "sat\n(model\n)\n");
+        // whitespace does not matter, but the solver does
+        assert!(cache.get(Backend::Cvc4, "(assert true)  (check-sat)").expect("cached") == "sat\n(model\n)\n");
+        assert!(cache.get(Backend::Z3, "(assert true)\n(check-sat)\n").is_none());
+        // except in string literals
+        cache.put(Backend::Cvc4, "(assert (= s \"a  b\"))", "unsat\n");
+        assert!(cache.get(Backend::Cvc4, "(assert  (= s \"a  b\"))").is_some());
+        assert!(cache.get(Backend::Cvc4, "(assert (= s \"a b\"))").is_none());
+        // nor are newlines, which do not end the key
+        cache.put(Backend::Cvc4, "(assert (= s \"a\nb\"))", "unsat\n");
+        assert!(cache.get(Backend::Cvc4, "(assert (= s \"a\nb\"))").expect("cached") == "unsat\n");
+        assert!(cache.get(Backend::Cvc4, "(assert (= s \"a\\nb\"))").is_none());
+        // what the solver does not know is asked again
+        cache.put(Backend::Cvc4, "(assert (f x))", "unknown\n");
+        assert!(cache.get(Backend::Cvc4, "(assert (f x))").is_none());
+        cache.clear();
+        assert!(cache.get(Backend::Cvc4, "(assert true)\n(check-sat)\n").is_none());
+        std::fs::remove_dir(&cache.dir).expect("cache should be empty");
+    }
+}
@@ -1,91 +1,1271 @@
-use std::collections::VecDeque;
-use std::rc::Rc;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::qry::Query;
-use crate::ctx::{Solution, Sort};
+use crate::qry::{Command, Query};
+use crate::ctx::{Constructor, Context, Solution, Sort};
 use crate::ast::{Term, Symbol};
+use crate::rwr::{inline, rename, simplify, substitute};
+use crate::smt::{Backend, Pool, SolverError};
+
+// limits on how much work solve may do, none by default
+#[derive(Default, Clone)]
+pub struct Budget {
+    // for the whole call to solve
+    pub timeout: Option<Duration>,
+    // for each call to the solver
+    pub solver_timeout: Option<Duration>,
+    // on the number of candidates taken off the queue
+    pub max_candidates: Option<usize>,
+    // on the number of CEGIS iterations, i.e. calls to the solver
+    pub max_iterations: Option<usize>,
+    // how many candidates can be with the solver at once, at least one
+    pub jobs: usize,
+    // how many threads enumerate candidates, at least one
+    pub threads: usize,
+    // give up as soon as this is set
+    pub stop: Option<Arc<AtomicBool>>,
+}
+
+// how to search for candidates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    // refine partial terms with holes, breadth first
+    TopDown,
+    // combine complete terms into bigger ones, smallest first
+    BottomUp,
+}
+
+// a way of running solve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub strategy: Strategy,
+    pub backend: Backend,
+    // leave out candidates the solver proves equivalent to ones seen before
+    pub prune: bool,
+    // leave holes for integer constants in candidates and ask the solver to fill them in
+    pub symbolic: bool,
+    // let candidates call the function to synthesize, on smaller arguments
+    pub recursive: bool,
+    // say why each refuted candidate is wrong, which costs an evaluation of the spec per symbol
+    pub explain: bool,
+}
+
+impl Config {
+    // every configuration, in the order a portfolio prefers them
+    pub fn all() -> Vec<Config> {
+        let mut configs = vec![];
+        for strategy in [Strategy::TopDown, Strategy::BottomUp] {
+            for backend in [Backend::Cvc4, Backend::Z3] {
+                configs.push(Config { strategy, backend, ..Config::default() });
+            }
+        }
+        configs
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { strategy: Strategy::TopDown, backend: Backend::Cvc4, prune: false, symbolic: false, recursive: false, explain: false }
+    }
+}
+
+impl Strategy {
+    pub fn new(name: &str) -> Strategy {
+        Strategy::parse(name).unwrap_or_else(|| panic!("unknown strategy {}", name))
+    }
+
+    pub fn parse(name: &str) -> Option<Strategy> {
+        match name {
+            "top-down" => Some(Strategy::TopDown),
+            "bottom-up" => Some(Strategy::BottomUp),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::TopDown => write!(f, "top-down"),
+            Strategy::BottomUp => write!(f, "bottom-up"),
+        }
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.model.keys().collect();
+        names.sort();
+        let values: Vec<String> = names.into_iter().map(|n| format!("{} = {}", n, self.model[n])).collect();
+        let sites: Vec<String> = self.sites.iter().map(|(site, call, v)| format!("{} is {} = {}", site, call, v)).collect();
+        let at = if values.is_empty() {"anywhere".to_owned()} else {format!("when {}", values.join(", "))};
+        write!(f, "{} fails {}", self.candidate, at)?;
+        if !sites.is_empty() {
+            write!(f, ", where {}", sites.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.strategy, self.backend)
+    }
+}
+
+// which limit ran out
+#[derive(Debug, PartialEq)]
+pub enum Limit {
+    Timeout,
+    SolverTimeout,
+    Candidates,
+    Iterations,
+    // someone else asked us to stop
+    Stopped,
+    // the solver could not answer
+    Failed(String),
+}
+
+// how far solve got before running out of budget
+pub struct Partial {
+    // the last complete candidate that passed every counterexample known at the time
+    pub best: Option<Arc<Term>>,
+    pub counterexamples: Vec<Solution>,
+    // why each candidate the solver refuted was wrong
+    pub explanations: Vec<Explanation>,
+    pub candidates: usize,
+    pub iterations: usize,
+}
+
+// a candidate the solver refuted, with the counterexample cut down to the declared symbols that
+// matter, and what the candidate gives at the calls to it they pin down
+#[derive(Clone, Debug)]
+pub struct Explanation {
+    pub index: usize,
+    pub candidate: Arc<Term>,
+    pub model: Solution,
+    // each call site, the call with the values of its arguments, and what the candidate gives there
+    pub sites: Vec<(Arc<Term>, Arc<Term>, Symbol)>,
+}
+
+// what a call to solve did and where the time went
+#[derive(Default, Clone, Debug)]
+pub struct Stats {
+    pub candidates: usize,
+    pub counterexamples: usize,
+    pub solver_calls: usize,
+    // candidates left out for being equivalent to ones seen before
+    pub pruned: usize,
+    // building candidates and testing them against the counterexamples
+    pub enumeration: Duration,
+    // waiting on the solver for complete candidates
+    pub verification: Duration,
+    pub total: Duration,
+    pub explanations: Vec<Explanation>,
+}
+
+// the next level of the search and the complete candidates found on the way,
+// and, when tracing, every candidate tested with the counterexample that killed it, if any
+type Found = (Vec<Arc<Term>>, Vec<Arc<Term>>, Vec<(Arc<Term>, Option<usize>)>);
+
+pub enum Outcome {
+    Solved(Arc<Term>),
+    NoSolution,
+    Exhausted(Limit, Partial),
+}
+
+// when to give up
+struct Clock {
+    deadline: Option<Instant>,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl Clock {
+    fn expired(&self) -> Option<Limit> {
+        if self.stop.as_ref().is_some_and(|s| s.load(Ordering::SeqCst)) {
+            Some(Limit::Stopped)
+        } else if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            Some(Limit::Timeout)
+        } else {
+            None
+        }
+    }
+
+    // how long we can wait on the solver before looking at the clock again
+    fn wait(&self) -> Option<Duration> {
+        let left = self.deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        match &self.stop {
+            Some(_) => Some(left.map_or(Duration::from_millis(100), |l| std::cmp::min(l, Duration::from_millis(100)))),
+            None => left
+        }
+    }
+}
+
+// complete terms built from the leafs up, by size
+struct Bank {
+    // every way of building a bigger term: the function, the sorts it takes and the sort it gives
+    ops: Vec<(Symbol, Vec<Sort>, Sort)>,
+    // the terms of each sort and size, starting at size one
+    terms: Vec<BTreeMap<Sort, Vec<Arc<Term>>>>,
+}
+
+// how many nodes a complete candidate can have for us to look for equivalent ones
+const MAX_EQUIVALENT_SIZE: usize = 7;
+// how many sample inputs to tell terms apart with
+const MAX_POINTS: usize = 64;
+// how many constants a candidate can leave for the solver to find
+const MAX_CONSTANT_HOLES: usize = 2;
+// how many times to look for constants for the same candidate, one more counterexample each time
+const MAX_CONSTANT_ROUNDS: usize = 8;
+
+// complete candidates seen so far, grouped by what they evaluate to at some sample inputs,
+// and the rewrites from redundant terms to equivalent ones we keep, like cvc4's sygus-rr.
+// A term with a redundant subterm is redundant too, on the assumption that the grammar can put
+// the equivalent term in its place.
+// The sample inputs the spec actually calls the function at come first: the arguments of its
+// ground call sites, and those of the others in each counterexample.
+// Only --prune uses them. Terms that agree at the sample inputs may still differ elsewhere, so
+// every equivalence they suggest costs a solver call to confirm, and neither search does that
+// otherwise: bottom-up has no observational equivalence of its own
+struct Classes {
+    params: Vec<(String, Sort)>,
+    // where the spec calls the function to synthesize, and the declared symbols in its arguments
+    sites: Vec<(Arc<Term>, Vec<String>)>,
+    // the inputs they give, so far
+    inputs: Vec<Vec<Symbol>>,
+    // the values to try for the parameters: some small ones and those in the counterexamples
+    values: BTreeSet<Symbol>,
+    // one context per sample input, with the parameters defined
+    points: Vec<Context>,
+    // a term for each class and its values at the points
+    reps: Vec<(Arc<Term>, Vec<Symbol>)>,
+    rules: BTreeMap<Arc<Term>, Arc<Term>>,
+}
+
+impl Classes {
+    fn new(params: &[(String, Sort)], sites: Vec<(Arc<Term>, Vec<String>)>) -> Classes {
+        let mut values = BTreeSet::new();
+        for (_, sort) in params {
+            match sort {
+                Sort::Bool => values.extend([Symbol::BoolLit(false), Symbol::BoolLit(true)]),
+                Sort::Int => values.extend((-1..3).map(Symbol::IntLit)),
+                _ => ()
+            }
+        }
+        Classes { params: params.to_vec(), sites, inputs: vec![], values, points: vec![], reps: vec![], rules: BTreeMap::new() }
+    }
+
+    // take in the values of new counterexamples, starting over on the sample inputs if there are any
+    fn update(&mut self, ctx: &Context, counterexamples: &[Solution]) {
+        let before = self.values.len();
+        for cex in counterexamples {
+            for v in cex.values() {
+                if v.get_args().len() == 0 && self.params.iter().any(|(_, s)| ctx.get_sort(v).as_ref() == Some(s)) {
+                    self.values.insert(v.get_symbol().clone());
+                }
+            }
+        }
+        let mut inputs = vec![];
+        for (site, needs) in &self.sites {
+            let models = if needs.is_empty() {&[Solution::new()][..]} else {counterexamples};
+            for input in models.iter().filter_map(|m| arguments(ctx, m, site, needs)) {
+                if !inputs.contains(&input) {
+                    inputs.push(input);
+                }
+            }
+        }
+        if before == self.values.len() && inputs == self.inputs && !self.points.is_empty() {
+            return
+        }
+        self.inputs = inputs;
+
+        // then every combination of values of the right sorts, up to a point
+        let mut combinations: Vec<Vec<Symbol>> = vec![vec![]];
+        for (_, sort) in &self.params {
+            let choices: Vec<&Symbol> = self.values.iter().filter(|v| Some(sort) == ctx.get_sort(&Term::mk_const((*v).clone())).as_ref()).collect();
+            combinations = combinations.iter()
+                .flat_map(|input| choices.iter().map(move |c| input.iter().cloned().chain(std::iter::once((*c).clone())).collect()))
+                .take(MAX_POINTS)
+                .collect();
+        }
+        let mut inputs = self.inputs.clone();
+        inputs.truncate(MAX_POINTS);
+        for input in combinations {
+            if inputs.len() < MAX_POINTS && !inputs.contains(&input) {
+                inputs.push(input);
+            }
+        }
+        // the parameters shadow whatever shares their names
+        let scope = ctx.with_params(&self.params);
+        self.points = inputs.into_iter().map(|input| {
+            let mut point = scope.clone();
+            for ((name, _), v) in self.params.iter().zip(input) {
+                point.add_body(name, Term::mk_const(v));
+            }
+            point
+        }).collect();
+        let reps = std::mem::take(&mut self.reps);
+        self.reps = reps.into_iter().map(|(t, _)| { let sig = self.signature(&t); (t, sig) }).collect();
+    }
+
+    fn signature(&self, t: &Term) -> Vec<Symbol> {
+        self.points.iter().map(|p| p.eval(&Solution::new(), t)).collect()
+    }
+
+    // does t have a subterm we know an equivalent for
+    fn redundant(&self, t: &Arc<Term>) -> bool {
+        redundant(&self.rules, t)
+    }
+}
+
+// the values of the arguments of a call in a model, if it has the symbols they need and they are all values
+fn arguments(ctx: &Context, model: &Solution, call: &Term, needs: &[String]) -> Option<Vec<Symbol>> {
+    if !needs.iter().all(|n| model.contains_key(n)) {
+        return None
+    }
+    call.get_args().map(|a| match ctx.eval(model, a) {
+        Symbol::NonTerm(_, _) | Symbol::Func(_) => None,
+        v => Some(v)
+    }).collect()
+}
+
+fn size(t: &Term) -> usize {
+    1 + t.get_args().map(|a| size(a)).sum::<usize>()
+}
+
+fn redundant(rules: &BTreeMap<Arc<Term>, Arc<Term>>, t: &Arc<Term>) -> bool {
+    rules.contains_key(t) || t.get_args().any(|a| redundant(rules, a))
+}
+
+// symmetry breaking: of the terms the laws of their operators make equal, keep only the one whose
+// operators are applied in order. That is: no argument is the identity, only the first argument
+// of an associative operator may be another application of it, and the arguments of a commutative
+// one are sorted (strictly, if it is also idempotent), looking through that first argument to its
+// last. Arguments still under construction can't be out of order yet
+fn in_order(ctx: &Context, t: &Term) -> bool {
+    if !t.get_args().all(|a| in_order(ctx, a)) {
+        return false
+    }
+    let props = match t.get_symbol() {
+        Symbol::Func(name) => match ctx.get_builtin(name) {
+            Some(f) => &f.props,
+            None => return true
+        },
+        _ => return true
+    };
+    let args: Vec<&Arc<Term>> = t.get_args().collect();
+    let nested = |a: &Term| props.associative && a.get_symbol() == t.get_symbol();
+    if let Some(e) = &props.identity {
+        if args.iter().any(|a| a.get_symbol() == e) {
+            return false
+        }
+    }
+    if args.iter().skip(1).any(|a| nested(a)) {
+        return false
+    }
+    if props.commutative {
+        for w in args.windows(2) {
+            let mut first = w[0];
+            while nested(first) {
+                first = first.get_args().last().expect("an application has arguments");
+            }
+            if !settled(first) || !settled(w[1]) {
+                continue
+            }
+            if first > w[1] || (props.idempotent && first == w[1]) {
+                return false
+            }
+        }
+    }
+    true
+}
+
+// do the calls to the function in t make its arguments smaller in some lexicographic order of the
+// parameters, where what we know on the way down says they can't get smaller forever: each call
+// makes one parameter smaller and passes those before it in the order as they are, so that no call
+// undoes what another did. We know the condition of an ite in its then branch and its negation in
+// the else branch, and likewise the earlier arguments of and, or and =>, which decide the value
+// without the later ones otherwise. Smaller is a field of a datatype parameter known to be built
+// with that constructor, or an integer parameter known to be above some bound minus a positive
+// constant
+fn terminates(ctx: &Context, name: &str, params: &[(String, Sort)], t: &Arc<Term>, facts: &mut Vec<Arc<Term>>) -> bool {
+    let mut calls: Vec<Call> = vec![];
+    collect_calls(name, t, facts, &mut calls);
+    let smaller_at = |i: usize, (args, facts): &Call| args.get(i).is_some_and(|a| smaller(ctx, &params[i].0, a, facts));
+    let same_at = |i: usize, (args, _): &Call| args.get(i).is_some_and(|a| is_param(&params[i].0, a));
+    // the next parameter in the order is one that every call left makes smaller or passes as it is,
+    // and then only the calls that pass it as it is are left
+    while !calls.is_empty() {
+        let next = (0..params.len()).find(|i| calls.iter().all(|c| smaller_at(*i, c) || same_at(*i, c)) && calls.iter().any(|c| smaller_at(*i, c)));
+        match next {
+            Some(i) => calls.retain(|c| !smaller_at(i, c)),
+            None => return false
+        }
+    }
+    true
+}
+
+// the arguments of a call to the function and what we know there
+type Call = (Vec<Arc<Term>>, Vec<Arc<Term>>);
+
+fn collect_calls(name: &str, t: &Arc<Term>, facts: &mut Vec<Arc<Term>>, calls: &mut Vec<Call>) {
+    let args: Vec<&Arc<Term>> = t.get_args().collect();
+    match t.get_symbol() {
+        Symbol::Func(f) if f == "ite" => {
+            collect_calls(name, args[0], facts, calls);
+            facts.push(Arc::clone(args[0]));
+            collect_calls(name, args[1], facts, calls);
+            facts.pop();
+            facts.push(negate(args[0]));
+            collect_calls(name, args[2], facts, calls);
+            facts.pop();
+        },
+        Symbol::Func(f) if f == "and" || f == "or" || f == "=>" => {
+            let before = facts.len();
+            for a in args {
+                collect_calls(name, a, facts, calls);
+                facts.push(if f == "or" {negate(a)} else {Arc::clone(a)});
+            }
+            facts.truncate(before);
+        },
+        _ => {
+            args.iter().for_each(|a| collect_calls(name, a, facts, calls));
+            if matches!(t.get_symbol(), Symbol::Func(f) if f == name) && !args.is_empty() {
+                calls.push((args.into_iter().cloned().collect(), facts.clone()));
+            }
+        }
+    }
+}
+
+fn negate(c: &Arc<Term>) -> Arc<Term> {
+    match c.get_symbol() {
+        Symbol::Func(f) if f == "not" => Arc::clone(c.get_args().next().expect("not has an argument")),
+        _ => Term::mk_app(Symbol::new("not"), vec![Arc::clone(c)])
+    }
+}
+
+// is a smaller than the parameter p, given the facts
+fn smaller(ctx: &Context, p: &str, a: &Term, facts: &[Arc<Term>]) -> bool {
+    decreasing(ctx, p, a) && match a.get_symbol() {
+        Symbol::Func(f) if f == "-" => facts.iter().any(|c| bounded(p, c)),
+        Symbol::Func(f) => {
+            let (cname, _, _) = ctx.get_selector(f).expect("unreachable");
+            built_with(ctx, a.get_args().next().expect("unreachable"), cname, facts)
+        },
+        _ => false
+    }
+}
+
+// is a the parameter p minus a positive constant, or one of its fields: smaller, if guarded
+fn decreasing(ctx: &Context, p: &str, a: &Term) -> bool {
+    let args: Vec<&Arc<Term>> = a.get_args().collect();
+    match a.get_symbol() {
+        Symbol::Func(f) if f == "-" && args.len() == 2 => is_param(p, args[0]) && matches!(args[1].get_symbol(), Symbol::IntLit(k) if *k > 0),
+        Symbol::Func(f) if args.len() == 1 => ctx.get_selector(f).is_some() && is_param(p, args[0]),
+        _ => false
+    }
+}
+
+// does t call the function on arguments that are complete and can't get smaller, however it is guarded
+fn unguardable(ctx: &Context, name: &str, params: &[(String, Sort)], t: &Term) -> bool {
+    let call = matches!(t.get_symbol(), Symbol::Func(f) if f == name) && t.get_args().len() > 0;
+    (call && t.get_args().all(|a| settled(a)) && !params.iter().zip(t.get_args()).any(|((p, _), a)| decreasing(ctx, p, a)))
+        || t.get_args().any(|a| unguardable(ctx, name, params, a))
+}
+
+fn is_param(p: &str, t: &Term) -> bool {
+    t.get_args().len() == 0 && matches!(t.get_symbol(), Symbol::Func(n) if n == p)
+}
+
+// does the fact say p is above some constant
+fn bounded(p: &str, c: &Term) -> bool {
+    let (negated, c) = match c.get_symbol() {
+        Symbol::Func(f) if f == "not" => (true, c.get_args().next().expect("not has an argument").as_ref()),
+        _ => (false, c)
+    };
+    let args: Vec<&Arc<Term>> = c.get_args().collect();
+    let literal = |t: &Term| matches!(t.get_symbol(), Symbol::IntLit(_));
+    match c.get_symbol() {
+        Symbol::Func(f) if args.len() == 2 => match (f.as_str(), negated) {
+            (">" | ">=", false) | ("<" | "<=", true) => is_param(p, args[0]) && literal(args[1]),
+            ("<" | "<=", false) | (">" | ">=", true) => literal(args[0]) && is_param(p, args[1]),
+            _ => false
+        },
+        _ => false
+    }
+}
+
+// is t known to be built with the constructor: it passes its tester, or fails every other one
+fn built_with(ctx: &Context, t: &Term, cname: &str, facts: &[Arc<Term>]) -> bool {
+    let tests = |c: &Term, k: &str| matches!(c.get_symbol(), Symbol::Func(f) if ctx.get_tester(f).is_some_and(|n| n == k))
+        && c.get_args().next().is_some_and(|a| a.as_ref() == t);
+    let fails = |k: &str| facts.iter().any(|c| matches!(c.get_symbol(), Symbol::Func(f) if f == "not")
+        && tests(c.get_args().next().expect("not has an argument"), k));
+    if facts.iter().any(|c| tests(c, cname)) {
+        return true
+    }
+    match ctx.get_constructor(cname) {
+        Some((Sort::Datatype(d), _)) => ctx.get_datatype(&d).expect("datatype must be declared").iter().all(|(k, _)| k == cname || fails(k)),
+        _ => false
+    }
+}
+
+fn is_hole(t: &Term) -> bool {
+    matches!(t.get_symbol(), Symbol::NonTerm(Sort::Int, n) if n == "const")
+}
+
+// complete but for holes for constants, which are all alike until the solver fills them in
+fn settled(t: &Term) -> bool {
+    is_hole(t) || (!matches!(t.get_symbol(), Symbol::NonTerm(_, _)) && t.get_args().all(|a| settled(a)))
+}
+
+fn holes(t: &Term) -> usize {
+    if is_hole(t) {1} else {t.get_args().map(|a| holes(a)).sum()}
+}
+
+// does the term apply something to nothing but literals and holes for constants, at least one of
+// them a hole. Whatever the solver puts in, that is a literal, so a hole or a literal leaf does the same
+fn constant_hole(t: &Term) -> bool {
+    let literal = |a: &Term| is_hole(a) || matches!(a.get_symbol(), Symbol::BoolLit(_) | Symbol::IntLit(_) | Symbol::StrLit(_));
+    (t.get_args().len() > 0 && t.get_args().all(|a| literal(a)) && t.get_args().any(|a| is_hole(a)))
+        || t.get_args().any(|a| constant_hole(a))
+}
+
+// the holes for constants become the constants c!0, c!1, ... from left to right
+fn number_holes(t: &Arc<Term>, next: &mut usize) -> Arc<Term> {
+    if is_hole(t) {
+        *next += 1;
+        return Term::mk_const(Symbol::Func(format!("c!{}", *next - 1)))
+    }
+    Term::new(t.get_symbol().clone(), t.get_args().map(|a| number_holes(a, next)).collect())
+}
+
+// op applied to every pair of terms from the list, in both orders and to the same term twice if
+// allowed (holes can always be taken twice), leaving out those not in order
+fn pairs(ctx: &Context, op: &str, order: &[Arc<Term>], twice: bool) -> Vec<Arc<Term>> {
+    let mut expansions = vec![];
+    for (i, a) in order.iter().enumerate() {
+        for (j, b) in order.iter().enumerate() {
+            if i == j && !twice && a.is_terminated() {
+                continue
+            }
+            let t = Term::mk_app(Symbol::new(op), vec![Arc::clone(a), Arc::clone(b)]);
+            if in_order(ctx, &t) {
+                expansions.push(t);
+            }
+        }
+    }
+    expansions
+}
 
 impl Query {
-    pub fn solve(&mut self) -> Option<Rc<Term>> {
-        // These are expansions of the grammar
-        let mut expns: VecDeque<Rc<Term>> = VecDeque::new();
-        // These are the counter-examples we have accumulated
-        let mut ctxs: Vec<Solution> = Vec::new();
+    pub fn solve(&mut self) -> Outcome {
+        let start = Instant::now();
+        let mut stats = Stats::default();
+        // the counterexamples we have accumulated, and how much work it took
+        // starting from whatever we were given that still fits the spec, once each
+        let mut seen = HashSet::new();
+        let seeds = self.get_counterexamples().iter()
+            .filter(|s| self.covers(s) && seen.insert(self.model_to_string(s)))
+            .cloned()
+            .collect();
+        let mut state = Partial { best: None, counterexamples: seeds, explanations: vec![], candidates: 0, iterations: 0 };
+        if let Some(trace) = self.get_recorder() {
+            trace.start(&self.get_config().to_string());
+        }
+        let result = self.search(&mut state, &mut stats);
+        self.set_counterexamples(state.counterexamples.clone());
+        stats.candidates = state.candidates;
+        stats.counterexamples = state.counterexamples.len();
+        stats.solver_calls = state.iterations;
+        stats.explanations = state.explanations.clone();
+        stats.total = start.elapsed();
+        debug!("{:?}", stats);
+        self.set_stats(stats);
+        if let Some(trace) = self.get_recorder() {
+            trace.done(match &result {
+                Ok(Some(_)) => "solved",
+                Ok(None) => "no-solution",
+                Err(Limit::Candidates) | Err(Limit::Iterations) => "budget-exhausted",
+                Err(Limit::Failed(_)) => "failed",
+                Err(_) => "timeout",
+            });
+        }
+        match result {
+            Ok(Some(body)) => Outcome::Solved(body),
+            Ok(None) => Outcome::NoSolution,
+            Err(limit) => Outcome::Exhausted(limit, state),
+        }
+    }
 
+    // the search itself: a solution, none if there is no solution, or the limit that ran out
+    fn search(&mut self, state: &mut Partial, stats: &mut Stats) -> Result<Option<Arc<Term>>, Limit> {
         let name = self.get_synth().expect("there must be a function to synthesize");
-        let (_, rsort) = self.peek_ctx().get_decl(name.as_str())
+        let (params, rsort) = self.peek_ctx().get_decl(name.as_str())
             .expect("synth has to have decl")
-            .first()
-            .expect("synth has to have only one decl");
+            .clone();
+
+        let budget = self.get_budget().clone();
+        let config = self.get_config();
+        let clock = Clock { deadline: budget.timeout.map(|t| Instant::now() + t), stop: budget.stop.clone() };
+        let mut pool = Pool::new(config.backend, budget.jobs, budget.solver_timeout, self.get_replay(), self.get_cache());
+
+        // These are expansions of the grammar, one level of the search at a time.
+        // Start with the start non terminal
+        let mut level = vec![Term::new(Symbol::NonTerm(rsort.clone(), "start".to_owned()), vec![])];
+        // or, bottom up, with nothing
+        let mut bank = Bank { ops: self.grammar_ops(&params), terms: vec![] };
+        // candidates with holes for constants, and how many times we have filled them in
+        let mut sketches: Vec<(Arc<Term>, usize)> = vec![];
 
-        // start with the start non terminal in expns
-        let start = Term::new(Symbol::NonTerm(*rsort, "start".to_owned()), vec![]);
-        expns.push_back(start);
+        // the simplified form of every complete candidate so far, so we ask about each only once
+        let mut seen = BTreeSet::new();
+        // and, when pruning, what they are equivalent to
+        let mut classes = Classes::new(&params, self.call_sites(&name));
+
+        // the hints go first
+        let hints = self.canonical(self.get_hints().to_vec(), &mut seen);
+        if !hints.is_empty() {
+            let ctx = self.peek_ctx().with_params(&params);
+            for h in &hints {
+                assert!(ctx.check_sort(h).as_ref() == Some(&rsort), "hint {} must be a {}", h, rsort);
+            }
+            state.candidates += hints.len();
+            let verifying = Instant::now();
+            let verified = self.verify(&name, hints, state, &mut pool, &budget, &clock);
+            stats.verification += verifying.elapsed();
+            if let Some(body) = verified? {
+                return Ok(Some(body))
+            }
+        }
 
         // if there are no expansions, return unsat
-        // else, pop an expansion
-        // - test it
+        // else, split the level between the threads, each of which goes through its part and
+        // - tests every expansion
         // - - if it fails any test then get rid of it
         // - if it survives then 
-        // - if it has no nonterminals, check if it is correct
-        // - - if it is correct, then return it
-        // - - if it is not, then get rid of it, and add the ctx to counters 
+        // - if it has no nonterminals, set it aside
         // - else it has nonterminals
-        // - - pick one and expand it in all possible ways, adding all to expns
+        // - - pick one and expand it in all possible ways, adding all to the next level
+        // then check the complete candidates in order
+        // - if one is correct, then return it
+        // - if it is not, then get rid of it, and add the ctx to counters 
+        // Candidates are accepted or refuted in the same order however many threads and solvers
+        // there are, so the answer is always the same.
+        // Bottom up, a level is every term of the next size
         loop {
-            match expns.pop_front() {
-                Some(body) => {
-                    self.add_body(name.as_str(), Rc::clone(&body));
-
-                    let mut failed = false;
-                    for ctx in &ctxs {
-                        failed = self.eval(&ctx) == Some(true);
-                        if failed {
-                            debug!("{} failed test {:?}", body, ctx);
-                            self.remove_body(name.as_str());
-                            break;
-                        }
+            if let Some(limit) = clock.expired() {
+                return Err(limit)
+            }
+            if budget.max_candidates.is_some_and(|m| state.candidates >= m) {
+                return Err(Limit::Candidates)
+            }
+
+            let enumerating = Instant::now();
+            let mut todo = match config.strategy {
+                Strategy::TopDown => std::mem::take(&mut level),
+                Strategy::BottomUp => self.grow(&mut bank, &params, &rsort).unwrap_or_default()
+            };
+            // If there is nothing left then we are done: no solution exists
+            if todo.is_empty() && sketches.is_empty() && (config.strategy == Strategy::TopDown || bank.ops.is_empty()) {
+                return Ok(None)
+            }
+            if let Some(m) = budget.max_candidates {
+                todo.truncate(m - state.candidates);
+            }
+
+            let found = self.search_level(&name, &todo, &state.counterexamples, &classes.rules, budget.threads, &clock);
+            stats.enumeration += enumerating.elapsed();
+            let (next, complete, tested) = match found {
+                Some(found) => found,
+                None => return Err(clock.expired().unwrap_or(Limit::Timeout))
+            };
+            if let Some(trace) = self.get_recorder() {
+                for (body, killer) in tested {
+                    trace.candidate(&body);
+                    if let Some(i) = killer {
+                        trace.killed(&body, i);
                     }
+                }
+            }
+            state.candidates += todo.len();
+            level = next;
 
-                    if failed {
-                        continue;
+            // sketches wait for constants that pass every counterexample, and get new ones with each new one
+            let (complete, found): (Vec<_>, Vec<_>) = complete.into_iter().partition(|t| t.is_terminated());
+            sketches.extend(found.into_iter().map(|t| (t, 0)));
+            let mut complete = self.canonical(complete, &mut seen);
+            let verifying = Instant::now();
+            if !sketches.is_empty() {
+                let filled = self.fill(&name, &mut sketches, state, &mut pool, &budget, &clock)?;
+                complete.extend(self.canonical(filled, &mut seen));
+            }
+            if config.prune {
+                let before = complete.len();
+                complete = self.prune(complete, &mut classes, state, &mut pool, &budget, &clock)?;
+                stats.pruned += before - complete.len();
+            }
+            let verified = self.verify(&name, complete, state, &mut pool, &budget, &clock);
+            stats.verification += verifying.elapsed();
+            // dropping the pool cancels the other checks
+            if let Some(body) = verified? {
+                return Ok(Some(body))
+            }
+        }
+    }
+
+    // race a copy of the query for every configuration, the first to find a solution wins.
+    // If none does, the outcome of the first configuration whose solver did not fail, or of the first
+    // if they all failed. The stats and counterexamples are the winner's
+    pub fn portfolio(&mut self, configs: &[Config]) -> (Config, Outcome) {
+        assert!(!configs.is_empty(), "portfolio needs a configuration");
+        let stop = Arc::new(AtomicBool::new(false));
+        let (winner, outcome, racer) = thread::scope(|scope| {
+            let (results, outcomes) = mpsc::channel();
+            for (i, config) in configs.iter().enumerate() {
+                let mut query = self.clone();
+                let Config { prune, symbolic, recursive, explain, .. } = self.get_config();
+                query.set_config(Config { prune, symbolic, recursive, explain, ..*config });
+                query.set_budget(Budget { stop: Some(Arc::clone(&stop)), ..self.get_budget().clone() });
+                let results = results.clone();
+                scope.spawn(move || {
+                    let outcome = query.solve();
+                    results.send((i, outcome, query)).ok();
+                });
+            }
+            drop(results);
+
+            let mut others: Vec<Option<(Outcome, Query)>> = configs.iter().map(|_| None).collect();
+            for (i, outcome, query) in outcomes {
+                debug!("{} finished", configs[i]);
+                if let Outcome::Solved(_) = outcome {
+                    // the others stop at their next look at the clock
+                    stop.store(true, Ordering::SeqCst);
+                    return (configs[i], outcome, query)
+                }
+                others[i] = Some((outcome, query));
+            }
+            // solvers fail e.g. when they are not installed
+            let failed = |o: &Option<(Outcome, Query)>| matches!(o, Some((Outcome::Exhausted(Limit::Failed(_), _), _)));
+            let i = others.iter().position(|o| !failed(o)).unwrap_or(0);
+            let (outcome, query) = others.swap_remove(i).expect("every configuration finishes");
+            (configs[i], outcome, query)
+        });
+        // what the winner learned, as if we had run it ourselves
+        self.set_stats(racer.get_stats().clone());
+        self.set_counterexamples(racer.get_counterexamples().to_vec());
+        (winner, outcome)
+    }
+
+    // test and expand a level of the search, in order, with up to threads threads.
+    // Returns the next level and the complete candidates, or None if we ran out of time
+    fn search_level(&self, name: &str, level: &[Arc<Term>], ctxs: &[Solution], rules: &BTreeMap<Arc<Term>, Arc<Term>>, threads: usize, clock: &Clock) -> Option<Found> {
+        let threads = std::cmp::max(threads, 1);
+        if threads == 1 || level.len() < 2 {
+            return self.search_part(name, level, ctxs, rules, clock)
+        }
+
+        // contiguous parts so that putting the results back together keeps the order
+        let size = level.len().div_ceil(threads);
+        let parts: Vec<Option<Found>> = thread::scope(|scope| {
+            let handles: Vec<_> = level.chunks(size)
+                .map(|part| scope.spawn(move || self.search_part(name, part, ctxs, rules, clock)))
+                .collect();
+            handles.into_iter().map(|h| h.join().expect("enumerator panicked")).collect()
+        });
+
+        let mut next = vec![];
+        let mut complete = vec![];
+        let mut tested = vec![];
+        for part in parts {
+            let (n, c, t) = part?;
+            next.extend(n);
+            complete.extend(c);
+            tested.extend(t);
+        }
+        Some((next, complete, tested))
+    }
+
+    fn search_part(&self, name: &str, part: &[Arc<Term>], ctxs: &[Solution], rules: &BTreeMap<Arc<Term>, Arc<Term>>, clock: &Clock) -> Option<Found> {
+        let (params, _) = self.peek_ctx().get_decl(name).expect("synth has to have decl");
+        let mut next = vec![];
+        let mut complete = vec![];
+        let mut tested = vec![];
+        let tracing = self.get_recorder().is_some();
+        for body in part {
+            if clock.expired().is_some() {
+                return None
+            }
+            // the same term with its arguments in order is somewhere else in the search
+            if !in_order(self.peek_ctx(), body) || holes(body) > MAX_CONSTANT_HOLES || constant_hole(body) {
+                continue
+            }
+            // recursive candidates must terminate, and we don't solve for their constants
+            if body.calls(name) && (unguardable(self.peek_ctx(), name, params, body)
+                || (settled(body) && (holes(body) > 0 || !terminates(self.peek_ctx(), name, params, body, &mut vec![])))) {
+                continue
+            }
+            let killer = self.killer(name, body, ctxs);
+            if tracing {
+                tested.push((Arc::clone(body), killer));
+            }
+            if killer.is_some() {
+                continue
+            }
+            // a whole family of terms with a part we know an equivalent for
+            if !rules.is_empty() && redundant(rules, body) {
+                continue
+            }
+            // the solver finds the constants for sketches
+            if settled(body) {
+                complete.push(Arc::clone(body));
+            } else {
+                next.extend(self.expand_term(Arc::clone(body)));
+            }
+        }
+        Some((next, complete, tested))
+    }
+
+    // leave out small candidates that are equivalent to one seen before: those that evaluate the same
+    // at the sample inputs as some earlier candidate, and that the solver can't tell apart from it
+    fn prune(&mut self, complete: Vec<Arc<Term>>, classes: &mut Classes, state: &mut Partial, pool: &mut Pool, budget: &Budget, clock: &Clock) -> Result<Vec<Arc<Term>>, Limit> {
+        classes.update(self.peek_ctx(), &state.counterexamples);
+        let name = self.get_synth().expect("there must be a function to synthesize");
+        let mut kept = vec![];
+        for t in complete {
+            if classes.redundant(&t) {
+                continue
+            }
+            // without its definition we can't evaluate or compare a recursive candidate
+            if size(&t) > MAX_EQUIVALENT_SIZE || t.calls(&name) {
+                kept.push(t);
+                continue
+            }
+            let sig = classes.signature(&t);
+            let same: Vec<Arc<Term>> = classes.reps.iter().filter(|(_, s)| s == &sig).map(|(r, _)| Arc::clone(r)).collect();
+            match self.equivalent(&t, &same, &classes.params, state, pool, budget, clock)? {
+                Some(r) => {
+                    debug!("{} is equivalent to {}", t, r);
+                    classes.rules.insert(t, r);
+                }
+                None => {
+                    classes.reps.push((Arc::clone(&t), sig));
+                    kept.push(t);
+                }
+            }
+        }
+        Ok(kept)
+    }
+
+    // the first of the others the solver proves equivalent to t, asking about all of them at once
+    #[allow(clippy::too_many_arguments)]
+    fn equivalent(&self, t: &Arc<Term>, others: &[Arc<Term>], params: &[(String, Sort)], state: &mut Partial, pool: &mut Pool, budget: &Budget, clock: &Clock) -> Result<Option<Arc<Term>>, Limit> {
+        // the parameters become constants, with names that can't clash with the ones declared
+        let fresh: HashMap<String, String> = params.iter().map(|(p, _)| (p.clone(), format!("{}!eq", p))).collect();
+        let mut ids = vec![];
+        for (i, other) in others.iter().enumerate() {
+            if budget.max_iterations.is_some_and(|m| state.iterations >= m) {
+                break
+            }
+            let mut query = self.background();
+            for (p, sort) in params {
+                query.declare_const(&fresh[p], &sort.to_string());
+            }
+            query.assert(Term::mk_app(Symbol::new("distinct"), vec![rename(&fresh, t), rename(&fresh, other)]));
+            query.check_sat();
+            let text = query.to_string();
+            if let Some(trace) = self.get_recorder() {
+                trace.query(state.iterations, t, &text);
+            }
+            pool.submit(i, text);
+            ids.push(state.iterations);
+            state.iterations += 1;
+        }
+
+        let mut answers = HashMap::new();
+        while pool.outstanding() > 0 {
+            if let Some(limit) = clock.expired() {
+                return Err(limit)
+            }
+            if let Some((i, answer)) = pool.recv(clock.wait()) {
+                answers.insert(i, answer);
+            }
+        }
+        // in order, so the same term is kept however the answers come back
+        for i in 0..ids.len() {
+            let answer = answers.remove(&i).expect("every question is answered");
+            if let Some(trace) = self.get_recorder() {
+                trace.answer(ids[i], &answer);
+            }
+            // only unsat means they are the same, if we can't tell then they are different
+            if answer.is_ok_and(|out| out.trim_start().starts_with("unsat")) {
+                return Ok(Some(Arc::clone(&others[i])))
+            }
+        }
+        Ok(None)
+    }
+
+    // CEGIS(T): for each sketch, constants that make it pass every counterexample so far, asking
+    // about all of them at once. A sketch is dropped once no constants will do, since there will
+    // only be more counterexamples, or once it has been filled in MAX_CONSTANT_ROUNDS times
+    fn fill(&self, name: &str, sketches: &mut Vec<(Arc<Term>, usize)>, state: &mut Partial, pool: &mut Pool, budget: &Budget, clock: &Clock) -> Result<Vec<Arc<Term>>, Limit> {
+        let mut queries = vec![];
+        let mut ids = vec![];
+        for (i, (sketch, _)) in sketches.iter().enumerate() {
+            if budget.max_iterations.is_some_and(|m| state.iterations >= m) {
+                break
+            }
+            let query = self.constant_query(name, sketch, &state.counterexamples);
+            let text = query.to_string();
+            if let Some(trace) = self.get_recorder() {
+                trace.query(state.iterations, sketch, &text);
+            }
+            pool.submit(i, text);
+            ids.push(state.iterations);
+            state.iterations += 1;
+            queries.push(query);
+        }
+
+        let mut answers = HashMap::new();
+        while pool.outstanding() > 0 {
+            if let Some(limit) = clock.expired() {
+                return Err(limit)
+            }
+            if let Some((i, answer)) = pool.recv(clock.wait()) {
+                answers.insert(i, answer);
+            }
+        }
+        let mut filled = vec![];
+        let mut kept = vec![];
+        for (i, (sketch, rounds)) in sketches.drain(..).enumerate() {
+            // the ones we had no budget to ask about wait for the next level
+            if i >= ids.len() {
+                kept.push((sketch, rounds));
+                continue
+            }
+            let answer = answers.remove(&i).expect("every question is answered");
+            if let Some(trace) = self.get_recorder() {
+                trace.answer(ids[i], &answer);
+            }
+            let model = match answer.and_then(|out| queries[i].read_answer(&out)) {
+                Ok(model) => model,
+                Err(_) => continue
+            };
+            let mut next = 0;
+            let body = number_holes(&sketch, &mut next);
+            if (0..next).any(|k| !model.contains_key(&format!("c!{}", k))) {
+                continue
+            }
+            debug!("filled in {} as {}", sketch, substitute(&model, &body));
+            filled.push(substitute(&model, &body));
+            if rounds + 1 < MAX_CONSTANT_ROUNDS {
+                kept.push((sketch, rounds + 1));
+            }
+        }
+        *sketches = kept;
+        Ok(filled)
+    }
+
+    // are there constants c!0, c!1, ... for the holes of the sketch under which it passes every
+    // counterexample. Each counterexample gets its own copy of the declared symbols, defined as in it
+    fn constant_query(&self, name: &str, sketch: &Arc<Term>, counterexamples: &[Solution]) -> Query {
+        fn params(ps: &[(String, Sort)]) -> Vec<(String, String)> {
+            ps.iter().map(|(p, s)| (p.clone(), s.to_string())).collect()
+        }
+        fn borrow(ps: &[(String, String)]) -> Vec<(&str, &str)> {
+            ps.iter().map(|(p, s)| (p.as_str(), s.as_str())).collect()
+        }
+        let mut next = 0;
+        let body = number_holes(sketch, &mut next);
+        let mut query = self.background();
+        for k in 0..next {
+            query.declare_const(&format!("c!{}", k), "Int");
+        }
+
+        let declared: Vec<&String> = self.into_iter().filter_map(|c| match c {
+            Command::Declare(n) => Some(n),
+            _ => None
+        }).collect();
+        let mut spec: Vec<Arc<Term>> = self.into_iter().filter_map(|c| match c {
+            Command::Assert(a) => Some(Arc::clone(a)),
+            _ => None
+        }).collect();
+        let spec = match spec.len() {
+            0 => Term::mk_const(Symbol::BoolLit(true)),
+            1 => spec.remove(0),
+            _ => Term::mk_app(Symbol::new("and"), spec),
+        };
+        // the sketch goes straight into the spec: defining it would need a logic with functions
+        let (ps, _) = self.peek_ctx().get_decl(name).expect("synth has to have decl");
+        let spec = inline(name, ps, &body, &spec);
+        for (i, cex) in counterexamples.iter().enumerate() {
+            let fresh: HashMap<String, String> = declared.iter().map(|n| ((*n).clone(), format!("{}!{}", n, i))).collect();
+            for n in &declared {
+                let (ps, s) = self.peek_ctx().get_decl(n).expect("declaration not found");
+                let value = cex.get(*n).expect("counterexamples define everything declared");
+                query.define_fun(&fresh[*n], borrow(&params(ps)), &s.to_string(), Arc::clone(value));
+            }
+            query.assert(Term::mk_app(Symbol::new("not"), vec![rename(&fresh, &spec)]));
+        }
+        query.check_sat();
+        query
+    }
+
+    // simplify complete candidates, leaving out those that simplify to something seen before.
+    // This is also how answers come out simplified
+    fn canonical(&self, complete: Vec<Arc<Term>>, seen: &mut BTreeSet<Arc<Term>>) -> Vec<Arc<Term>> {
+        complete.iter()
+            .map(|t| simplify(self.peek_ctx(), t))
+            .filter(|t| seen.insert(Arc::clone(t)))
+            .collect()
+    }
+
+    // every distinct call to the function to synthesize in the spec, in order of first appearance,
+    // and the declared symbols its arguments mention. Those that mention none are examples, the
+    // others sample an input in each counterexample
+    fn call_sites(&self, name: &str) -> Vec<(Arc<Term>, Vec<String>)> {
+        fn collect(name: &str, t: &Arc<Term>, sites: &mut Vec<Arc<Term>>) {
+            t.get_args().for_each(|a| collect(name, a, sites));
+            if matches!(t.get_symbol(), Symbol::Func(n) if n == name) && !sites.contains(t) {
+                sites.push(Arc::clone(t));
+            }
+        }
+        fn mentions(declared: &[&String], t: &Term, needs: &mut Vec<String>) {
+            if let Symbol::Func(n) = t.get_symbol() {
+                if declared.contains(&n) && !needs.contains(n) {
+                    needs.push(n.clone());
+                }
+            }
+            t.get_args().for_each(|a| mentions(declared, a, needs));
+        }
+        let mut sites = vec![];
+        let mut declared = vec![];
+        for c in self {
+            match c {
+                Command::Assert(a) => collect(name, a, &mut sites),
+                Command::Declare(n) => declared.push(n),
+                _ => ()
+            }
+        }
+        sites.into_iter().map(|s| {
+            let mut needs = vec![];
+            s.get_args().for_each(|a| mentions(&declared, a, &mut needs));
+            (s, needs)
+        }).collect()
+    }
+
+    // the calls a counterexample makes the spec depend on: each call site with the values of its
+    // arguments in it
+    fn constrained(&self, name: &str, cex: &Solution) -> Vec<String> {
+        self.call_sites(name).iter()
+            .filter_map(|(site, needs)| arguments(self.peek_ctx(), cex, site, needs).map(|input| {
+                let args: Vec<Arc<Term>> = input.into_iter().map(Term::mk_const).collect();
+                format!("{} at {}", site, Term::mk_app(site.get_symbol().clone(), args))
+            }))
+            .collect()
+    }
+
+    // the counterexample without the declared symbols the candidate fails whatever their values:
+    // each in turn is left unknown, and stays so if the spec still evaluates to false
+    fn minimize(&self, name: &str, body: &Arc<Term>, cex: &Solution) -> Solution {
+        let mut model = cex.clone();
+        model.insert(name.to_owned(), Arc::clone(body));
+        let mut kept = Solution::new();
+        for c in self {
+            if let Command::Declare(n) = c {
+                let value = match model.get(n) {
+                    Some(v) => Arc::clone(v),
+                    None => continue
+                };
+                let (_, sort) = self.peek_ctx().get_decl(n).expect("declared");
+                model.insert(n.clone(), Term::mk_const(Symbol::NonTerm(sort.clone(), "any".to_owned())));
+                if self.eval(&model) != Some(true) {
+                    model.insert(n.clone(), Arc::clone(&value));
+                    kept.insert(n.clone(), value);
+                }
+            }
+        }
+        kept
+    }
+
+    fn explain(&self, name: &str, body: &Arc<Term>, cex: &Solution, index: usize) -> Explanation {
+        let model = self.minimize(name, body, cex);
+        let mut candidate = Solution::new();
+        candidate.insert(name.to_owned(), Arc::clone(body));
+        let sites = self.call_sites(name).into_iter()
+            .filter_map(|(site, needs)| arguments(self.peek_ctx(), &model, &site, &needs).map(|input| {
+                let call = Term::mk_app(site.get_symbol().clone(), input.into_iter().map(Term::mk_const).collect());
+                let value = self.peek_ctx().eval(&candidate, &call);
+                (site, call, value)
+            }))
+            .collect();
+        Explanation { index, candidate: Arc::clone(body), model, sites }
+    }
+
+    // does the model give a value to everything that is declared, so we can evaluate the spec in it
+    fn covers(&self, sol: &Solution) -> bool {
+        self.into_iter().all(|c| match c {
+            Command::Declare(name) => sol.contains_key(name),
+            _ => true
+        })
+    }
+
+    // does the candidate body make any counterexample true
+    fn fails(&self, name: &str, body: &Arc<Term>, ctxs: &[Solution]) -> bool {
+        self.killer(name, body, ctxs).is_some()
+    }
+
+    // the first counterexample the candidate body makes true
+    fn killer(&self, name: &str, body: &Arc<Term>, ctxs: &[Solution]) -> Option<usize> {
+        ctxs.iter().position(|ctx| {
+            let mut s = ctx.clone();
+            s.insert(name.to_owned(), Arc::clone(body));
+            let failed = self.eval(&s) == Some(true);
+            if failed {
+                debug!("{} failed test {:?}", body, ctx);
+            }
+            failed
+        })
+    }
+
+    // ask the oracle about complete candidates, keeping every solver in the pool busy.
+    // Answers are used in order: a candidate is only accepted or refuted once every candidate
+    // before it has been, and is skipped if a counterexample for an earlier one rules it out
+    fn verify(&mut self, name: &str, complete: Vec<Arc<Term>>, state: &mut Partial, pool: &mut Pool, budget: &Budget, clock: &Clock) -> Result<Option<Arc<Term>>, Limit> {
+        let mut answers: HashMap<usize, Result<String, SolverError>> = HashMap::new();
+        let mut sent = vec![false; complete.len()];
+        // the number of each query over the whole search, for the trace
+        let mut ids = vec![0; complete.len()];
+        // those a counterexample ruled out while they were with the solver, whose answers we drop
+        let mut dropped = vec![false; complete.len()];
+        // the next candidate to send and the next to decide
+        let mut next = 0;
+        let mut head = 0;
+        while head < complete.len() {
+            while next < complete.len() && pool.outstanding() < pool.size() {
+                if !self.fails(name, &complete[next], &state.counterexamples) {
+                    if budget.max_iterations.is_some_and(|m| state.iterations >= m) {
+                        break
+                    }
+                    self.add_body(name, Arc::clone(&complete[next]));
+                    let text = format!("{}", self);
+                    self.remove_body(name);
+                    if let Some(trace) = self.get_recorder() {
+                        trace.query(state.iterations, &complete[next], &text);
                     }
+                    pool.submit(next, text);
+                    ids[next] = state.iterations;
+                    state.iterations += 1;
+                    sent[next] = true;
+                }
+                next += 1;
+            }
+            // nothing is with the solver and we can't send any more
+            if head == next {
+                return Err(Limit::Iterations)
+            }
+            if !sent[head] {
+                head += 1;
+                continue
+            }
+            // a counterexample we learned after sending it may already rule it out, and then we
+            // don't wait for its answer
+            if self.fails(name, &complete[head], &state.counterexamples) {
+                if let Some(answer) = answers.remove(&head) {
+                    if let Some(trace) = self.get_recorder() {
+                        trace.answer(ids[head], &answer);
+                    }
+                } else {
+                    dropped[head] = true;
+                }
+                head += 1;
+                continue
+            }
 
-                    if body.is_terminated() {
-                        // ask the oracle if it is correct
-                        let new_ctx = self.check_cvc4().expect("could not parse");
-                        self.remove_body(name.as_str());
-                        if new_ctx.is_empty() {
-                            return Some(body)
+            while !answers.contains_key(&head) {
+                if let Some(limit) = clock.expired() {
+                    return Err(limit)
+                }
+                if let Some((id, answer)) = pool.recv(clock.wait()) {
+                    if dropped[id] {
+                        if let Some(trace) = self.get_recorder() {
+                            trace.answer(ids[id], &answer);
                         }
-                        ctxs.push(new_ctx);
-                        continue;
+                    } else {
+                        answers.insert(id, answer);
                     }
+                }
+            }
+            let answer = answers.remove(&head).expect("unreachable");
+            let body = &complete[head];
+            let id = ids[head];
+            head += 1;
+            if let Some(trace) = self.get_recorder() {
+                trace.answer(id, &answer);
+            }
 
-                    for expansion in self.expand_term(body) {
-                        expns.push_back(expansion);
+            state.best = Some(Arc::clone(body));
+            match answer.and_then(|out| self.read_answer(&out)) {
+                Ok(ctx) => {
+                    if ctx.is_empty() {
+                        return Ok(Some(Arc::clone(body)))
                     }
-
-                    self.remove_body(name.as_str());
+                    let constrained = self.constrained(name, &ctx);
+                    debug!("counterexample {} constrains {}", state.counterexamples.len(), constrained.join("; "));
+                    // only verbose output and traces say why
+                    let recorder = self.get_recorder();
+                    if self.get_config().explain || recorder.is_some() {
+                        let explanation = self.explain(name, body, &ctx, state.counterexamples.len());
+                        debug!("counterexample {}: {}", explanation.index, explanation);
+                        if let Some(trace) = recorder {
+                            let values: Vec<String> = explanation.sites.iter().map(|(site, call, v)| format!("{} at {} is {}", site, call, v)).collect();
+                            trace.counterexample(state.counterexamples.len(), id, &constrained, body, &self.model_to_string(&explanation.model), &values);
+                        }
+                        if self.get_config().explain {
+                            state.explanations.push(explanation);
+                        }
+                    }
+                    state.counterexamples.push(ctx);
                 }
-                // If there is nothing to pop then we are done: no solution exists
-                None => return None
+                Err(SolverError::Timeout) => return Err(clock.expired().unwrap_or(Limit::SolverTimeout)),
+                Err(e) => return Err(Limit::Failed(e.to_string()))
             }
         }
+        // the dropped answers still to come, so they don't get taken for answers to later questions
+        while pool.outstanding() > 0 {
+            if let Some(limit) = clock.expired() {
+                return Err(limit)
+            }
+            if let Some((id, answer)) = pool.recv(clock.wait()) {
+                if let Some(trace) = self.get_recorder() {
+                    trace.answer(ids[id], &answer);
+                }
+            }
+        }
+        Ok(None)
     }
 
-    fn expand_term(&self, t: Rc<Term>) -> Vec<Rc<Term>> {
+    fn expand_term(&self, t: Arc<Term>) -> Vec<Arc<Term>> {
 
         match t.get_symbol() {
             Symbol::BoolLit(_)
-            | Symbol::IntLit(_) => vec! [Rc::clone(&t)],
-            Symbol::Func(name) => {
-                let mut expansions : Vec<Rc<Term>> = vec![];
-                let mut variations : Vec<Rc<Term>> = vec![];
+            | Symbol::IntLit(_)
+            | Symbol::StrLit(_)
+            | Symbol::ArrayLit(_, _, _)
+            | Symbol::DtLit(_, _, _) => vec! [Arc::clone(&t)],
+            Symbol::Func(_)
+            | Symbol::ConstArray(_) => {
+                let mut expansions : Vec<Arc<Term>> = vec![];
+                let mut variations : Vec<Arc<Term>> = vec![];
 
                 let mut idx = 0;
                 for a in t.get_args() {
-                    variations = self.expand_term(Rc::clone(a));
+                    variations = self.expand_term(Arc::clone(a));
                     if variations.len() > 1 {
                         break;
                     }
@@ -97,33 +1277,31 @@ impl Query {
                     let mut j = 0;
                     for a in t.get_args() {
                         if j == idx {
-                            tmp.push(Rc::clone(&v));
+                            tmp.push(Arc::clone(&v));
                         } else {
-                            tmp.push(Rc::clone(a));
+                            tmp.push(Arc::clone(a));
                         }
                         j += 1;
                     }
-                    expansions.push(Term::new(Symbol::Func(name.clone()), tmp));
+                    expansions.push(Term::new(t.get_symbol().clone(), tmp));
                 }
 
                 expansions
             }
             Symbol::NonTerm(rsort, nt) => {
                 let (params, _) = self.peek_ctx().get_decl(self.get_synth().as_ref().expect("there must be a function to synthesize").as_str())
-                    .expect("synth has to have decl")
-                    .first()
-                    .expect("synth has to have only one decl");
+                    .expect("synth has to have decl");
                 self.expand_nt(nt, rsort, params)
             } 
         }
     }
 
-    fn expand_nt(&self, nt: &String, rsort: &Sort, leafs: &Vec<(String, Sort)>) -> Vec<Rc<Term>> {
+    fn expand_nt(&self, nt: &String, rsort: &Sort, leafs: &Vec<(String, Sort)>) -> Vec<Arc<Term>> {
         let mut expansions = vec![];
         match nt.as_str() {
             "start" => {
-                expansions.push(Term::mk_const(Symbol::NonTerm(*rsort, "leafs".to_owned())));
-                expansions.push(Term::mk_const(Symbol::NonTerm(*rsort, "op".to_owned())));
+                expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), "leafs".to_owned())));
+                expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), "op".to_owned())));
             }
             "leafs" => {
                 match rsort {
@@ -145,9 +1323,47 @@ impl Query {
                                 expansions.push(Term::mk_const(Symbol::new(iname.as_str())));
                             }
                         }
-                        // add zero and one
-                        expansions.push(Term::mk_const(Symbol::IntLit(0)));
-                        expansions.push(Term::mk_const(Symbol::IntLit(1)));
+                        // add zero, one and the constants in the specification
+                        expansions.extend(self.int_constants());
+                    },
+                    Sort::String => {
+                        // add all the string leafs
+                        for (iname, isort) in leafs {
+                            if isort == &Sort::String {
+                                expansions.push(Term::mk_const(Symbol::new(iname.as_str())));
+                            }
+                        }
+                        // add the empty string and every string in the specification
+                        expansions.push(Term::mk_const(Symbol::StrLit(String::new())));
+                        for l in self.spec_literals() {
+                            if let Symbol::StrLit(v) = &l {
+                                if !v.is_empty() {
+                                    expansions.push(Term::mk_const(l));
+                                }
+                            }
+                        }
+                    },
+                    Sort::Array(_, _) => {
+                        // add all the array leafs of this exact sort
+                        for (iname, isort) in leafs {
+                            if isort == rsort {
+                                expansions.push(Term::mk_const(Symbol::new(iname.as_str())));
+                            }
+                        }
+                    },
+                    Sort::Datatype(dname) => {
+                        // add all the datatype leafs of this exact sort
+                        for (iname, isort) in leafs {
+                            if isort == rsort {
+                                expansions.push(Term::mk_const(Symbol::new(iname.as_str())));
+                            }
+                        }
+                        // add the constructors without fields
+                        for (cname, fields) in self.peek_ctx().get_datatype(dname).expect("datatype must be declared") {
+                            if fields.is_empty() {
+                                expansions.push(Term::mk_const(Symbol::new(cname.as_str())));
+                            }
+                        }
                     }
                 }
             }
@@ -166,6 +1382,7 @@ impl Query {
                             expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Bool, ">".to_owned())));
                             expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Bool, ">=".to_owned())));
                         }
+
                     },
                     Sort::Int => {
                         // add general operators
@@ -173,34 +1390,83 @@ impl Query {
                         expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "+".to_owned())));
                         expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "-".to_owned())));
                         expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "*".to_owned())));
+                    },
+                    Sort::Datatype(_) => {
+                        expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), "construct".to_owned())));
+                    }
+                    _ => ()
+                }
+                let name = self.get_synth().expect("there must be a function to synthesize");
+                let (_, fsort) = self.peek_ctx().get_decl(&name).expect("synth has to have decl");
+                if self.get_config().recursive && rsort == fsort {
+                    expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), "call".to_owned())));
+                }
+                if self.components(leafs).iter().any(|(_, _, csort)| csort == rsort) {
+                    expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), "component".to_owned())));
+                }
+                // every other interpreted symbol that can produce this sort
+                let sorts = self.grammar_sorts(leafs);
+                for (fname, f) in self.peek_ctx().get_builtins() {
+                    if f.instances(rsort, &sorts).iter().any(|ps| !hand_tuned(fname, ps)) {
+                        expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), fname.clone())));
+                    }
+                }
+                // take apart any datatype argument with a field of the right sort
+                let inputs = self.input_datatypes(leafs);
+                if inputs.iter().any(|(_, ctors)| ctors.iter().any(|(_, fields)| fields.iter().any(|(_, fsort)| fsort == rsort))) {
+                    expansions.push(Term::mk_const(Symbol::NonTerm(rsort.clone(), "selector".to_owned())));
+                }
+                if rsort == &Sort::Bool && !inputs.is_empty() {
+                    expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Bool, "tester".to_owned())));
+                }
+            }
+            "construct" => {
+                if let Sort::Datatype(dname) = rsort {
+                    for (cname, fields) in self.peek_ctx().get_datatype(dname).expect("datatype must be declared") {
+                        if !fields.is_empty() {
+                            let args = fields.iter().map(|(_, fsort)| Term::mk_const(Symbol::NonTerm(fsort.clone(), "start".to_owned()))).collect();
+                            expansions.push(Term::mk_app(Symbol::new(cname.as_str()), args));
+                        }
                     }
-                }                
+                }
+            }
+            "selector" => {
+                for (dsort, ctors) in self.input_datatypes(leafs) {
+                    for (_, fields) in ctors {
+                        for (sname, fsort) in fields {
+                            if fsort == rsort {
+                                let arg = Term::mk_const(Symbol::NonTerm(dsort.clone(), "start".to_owned()));
+                                expansions.push(Term::mk_app(Symbol::new(sname.as_str()), vec![arg]));
+                            }
+                        }
+                    }
+                }
+            }
+            "tester" => {
+                assert!(rsort == &Sort::Bool);
+                for (dsort, ctors) in self.input_datatypes(leafs) {
+                    for (cname, _) in ctors {
+                        let arg = Term::mk_const(Symbol::NonTerm(dsort.clone(), "start".to_owned()));
+                        expansions.push(Term::mk_app(Symbol::Func(format!("is-{}", cname)), vec![arg]));
+                    }
+                }
             }
             "+"
             |"-" => {
                 assert!(rsort == &Sort::Int);
                 let mut order = vec![];
-                order.push(Term::mk_const(Symbol::IntLit(0)));
-                order.push(Term::mk_const(Symbol::IntLit(1)));
+                order.extend(self.int_constants());
                 for (iname, isort) in leafs {
                     if isort == &Sort::Int {
                         order.push(Term::mk_const(Symbol::new(iname.as_str())));
                     }
                 }
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "op".to_owned())));
-
-                // enforce simple commutativity based on order
-                for i in 0..order.len()-1 {
-                    for j in i+1..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new(nt.as_str()), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
-                    }
-                }
+                expansions.extend(pairs(self.peek_ctx(), nt, &order, false));
             }
             "*" => {
                 assert!(rsort == &Sort::Int);
-                let mut constants = vec![];
-                constants.push(Term::mk_const(Symbol::IntLit(0)));
-                constants.push(Term::mk_const(Symbol::IntLit(1)));
+                let mut constants = self.int_constants();
                 constants.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "c-op".to_owned())));
                 
                 let mut vars = vec![];
@@ -211,10 +1477,15 @@ impl Query {
                 }
                 vars.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "op".to_owned())));
 
-                // enforce simple commutativity based on order
-                for c in constants {
+                // linear: a constant times something, on either side
+                for c in &constants {
                     for v in &vars {
-                        expansions.push(Term::mk_app(Symbol::new("*"), vec![Rc::clone(&c), Rc::clone(&v)]));
+                        for args in [vec![Arc::clone(c), Arc::clone(v)], vec![Arc::clone(v), Arc::clone(c)]] {
+                            let t = Term::mk_app(Symbol::new("*"), args);
+                            if in_order(self.peek_ctx(), &t) {
+                                expansions.push(t);
+                            }
+                        }
                     }
                 }
             }
@@ -229,18 +1500,17 @@ impl Query {
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Bool, "op".to_owned())));
 
                 let bool_op = Term::mk_const(Symbol::NonTerm(Sort::Bool, "op".to_owned()));
-                // enforce simple commutativity based on order
+                // (ite c a b) is (ite (not c) b a), so only one order of the branches is needed
                 for i in 0..order.len()-1 {
                     for j in i+1..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("ite"), vec![Rc::clone(&bool_op), Rc::clone(&order[i]), Rc::clone(&order[j])]));
+                        expansions.push(Term::mk_app(Symbol::new("ite"), vec![Arc::clone(&bool_op), Arc::clone(&order[i]), Arc::clone(&order[j])]));
                     }
                 }
             }
             "itei" => {
                 assert!(rsort == &Sort::Int);
                 let mut order = vec![];
-                order.push(Term::mk_const(Symbol::IntLit(0)));
-                order.push(Term::mk_const(Symbol::IntLit(1)));
+                order.extend(self.int_constants());
                 for (iname, isort) in leafs {
                     if isort == &Sort::Int {
                         order.push(Term::mk_const(Symbol::new(iname.as_str())));
@@ -249,10 +1519,10 @@ impl Query {
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "op".to_owned())));
 
                 let bool_op = Term::mk_const(Symbol::NonTerm(Sort::Bool, "op".to_owned()));
-                // enforce simple commutativity based on order
+                // (ite c a b) is (ite (not c) b a), so only one order of the branches is needed
                 for i in 0..order.len()-1 {
                     for j in i+1..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("ite"), vec![Rc::clone(&bool_op), Rc::clone(&order[i]), Rc::clone(&order[j])]));
+                        expansions.push(Term::mk_app(Symbol::new("ite"), vec![Arc::clone(&bool_op), Arc::clone(&order[i]), Arc::clone(&order[j])]));
                     }
                 }
             }
@@ -267,13 +1537,7 @@ impl Query {
                     }
                 }
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Bool, "op".to_owned())));
-
-                // enforce simple commutativity based on order
-                for i in 0..order.len()-1 {
-                    for j in i+1..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new(nt.as_str()), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
-                    }
-                }
+                expansions.extend(pairs(self.peek_ctx(), nt, &order, false));
             }
             "not" => {
                 assert!(rsort == &Sort::Bool);
@@ -292,15 +1556,11 @@ impl Query {
                         choices.push(Term::mk_const(Symbol::new(iname.as_str())));
                     }
                 }
+                // for thresholds
+                choices.extend(self.int_constants());
                 choices.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "op".to_owned())));
 
-                for i in 0..choices.len() {
-                    for j in 0+1..choices.len() {
-                        if i != j {
-                            expansions.push(Term::mk_app(Symbol::new(nt.as_str()), vec![Rc::clone(&choices[i]), Rc::clone(&choices[j])]));
-                        }
-                    }
-                }
+                expansions.extend(pairs(self.peek_ctx(), nt, &choices, false));
             }
             "=b" => {
                 let mut order = vec![];
@@ -310,30 +1570,37 @@ impl Query {
                     }
                 }
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Bool, "op".to_owned())));
-                // enforce simple commutativity based on order
-                for i in 0..order.len()-1 {
-                    for j in i+1..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("="), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
-                    }
-                }
+                expansions.extend(pairs(self.peek_ctx(), "=", &order, false));
             }
             "=i" => {
                 let mut order = vec![];
-                order.push(Term::mk_const(Symbol::IntLit(0)));
-                order.push(Term::mk_const(Symbol::IntLit(1)));
+                order.extend(self.int_constants());
                 for (iname, isort) in leafs {
                     if isort == &Sort::Int {
                         order.push(Term::mk_const(Symbol::new(iname.as_str())));
                     }
                 }
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "op".to_owned())));
-                // enforce simple commutativity based on order
-                for i in 0..order.len()-1 {
-                    for j in i+1..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("="), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
+                expansions.extend(pairs(self.peek_ctx(), "=", &order, false));
+            }
+            "call" => {
+                // the function to synthesize itself, on anything: terminates decides what is smaller
+                let name = self.get_synth().expect("there must be a function to synthesize");
+                let args = leafs.iter().map(|(_, psort)| Term::mk_const(Symbol::NonTerm(psort.clone(), "start".to_owned()))).collect();
+                expansions.push(Term::mk_app(Symbol::Func(name), args));
+            }
+            "component" => {
+                for (cname, psorts, csort) in self.components(leafs) {
+                    if &csort == rsort {
+                        let args = psorts.into_iter().map(|psort| Term::mk_const(Symbol::NonTerm(psort, "start".to_owned()))).collect();
+                        expansions.push(Term::mk_app(Symbol::Func(cname), args));
                     }
                 }
             }
+            "const" => {
+                // stays a hole until the solver fills it in
+                expansions.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "const".to_owned())));
+            }
             "c-op" => {
                 assert!(rsort == &Sort::Int);
                 // add general operators
@@ -346,13 +1613,7 @@ impl Query {
                 let mut order = vec![];
                 order.push(Term::mk_const(Symbol::IntLit(1)));
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "c-op".to_owned())));
-
-                // enforce simple commutativity based on order
-                for i in 0..order.len()-1 {
-                    for j in i..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("+"), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
-                    }
-                }
+                expansions.extend(pairs(self.peek_ctx(), "+", &order, true));
             }
             "c-" => {
                 assert!(rsort == &Sort::Int);
@@ -360,22 +1621,20 @@ impl Query {
                 order.push(Term::mk_const(Symbol::IntLit(0)));
                 order.push(Term::mk_const(Symbol::IntLit(1)));
                 order.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "c-op".to_owned())));
-
-                for i in 0..order.len()-1 {
-                    for j in i..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("-"), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
-                    }
-                }
+                expansions.extend(pairs(self.peek_ctx(), "-", &order, true));
             }
             "c*" => {
                 assert!(rsort == &Sort::Int);
-                let mut order = vec![];
-                order.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "c-op".to_owned())));
-
-                // enforce simple commutativity based on order
-                for i in 0..order.len()-1 {
-                    for j in i..order.len() {
-                        expansions.push(Term::mk_app(Symbol::new("*"), vec![Rc::clone(&order[i]), Rc::clone(&order[j])]));
+                let order = vec![Term::mk_const(Symbol::NonTerm(Sort::Int, "c-op".to_owned()))];
+                expansions.extend(pairs(self.peek_ctx(), "*", &order, true));
+            }
+            _ if self.peek_ctx().get_builtin(nt).is_some() => {
+                // every argument is a fresh start non-terminal of the sort the operator expects
+                let f = self.peek_ctx().get_builtin(nt).expect("unreachable");
+                for ps in f.instances(rsort, &self.grammar_sorts(leafs)) {
+                    if !hand_tuned(nt, &ps) {
+                        let args = ps.into_iter().map(|psort| Term::mk_const(Symbol::NonTerm(psort, "start".to_owned()))).collect();
+                        expansions.push(Term::mk_app(Symbol::new(nt.as_str()), args));
                     }
                 }
             }
@@ -383,4 +1642,536 @@ impl Query {
         }
         expansions
     }
-}
\ No newline at end of file
+
+    // the integer constants to build terms with: zero, one and those in the specification, and, with
+    // symbolic constants, a hole for the solver to fill in with any other
+    fn int_constants(&self) -> Vec<Arc<Term>> {
+        let mut constants = vec![Term::mk_const(Symbol::IntLit(0)), Term::mk_const(Symbol::IntLit(1))];
+        for l in self.spec_literals() {
+            if matches!(l, Symbol::IntLit(v) if v != 0 && v != 1) {
+                constants.push(Term::mk_const(l));
+            }
+        }
+        if self.get_config().symbolic {
+            constants.push(Term::mk_const(Symbol::NonTerm(Sort::Int, "const".to_owned())));
+        }
+        constants
+    }
+
+    // the literals that appear in the assertions, in order of first appearance
+    fn spec_literals(&self) -> Vec<Symbol> {
+        fn collect(t: &Arc<Term>, lits: &mut Vec<Symbol>) {
+            match t.get_symbol() {
+                Symbol::BoolLit(_)
+                | Symbol::IntLit(_)
+                | Symbol::StrLit(_) => if !lits.contains(t.get_symbol()) {
+                    lits.push(t.get_symbol().clone())
+                },
+                _ => t.get_args().for_each(|a| collect(a, lits))
+            }
+        }
+        let mut lits = vec![];
+        for c in self {
+            if let Command::Assert(a) = c {
+                collect(a, &mut lits);
+            }
+        }
+        lits
+    }
+
+    // every function we build bigger terms with bottom up: the interpreted ones at their smallest
+    // arity (but not mirror images like < for >), and the constructors, selectors and testers
+    fn grammar_ops(&self, leafs: &[(String, Sort)]) -> Vec<(Symbol, Vec<Sort>, Sort)> {
+        let sorts = self.grammar_sorts(leafs);
+        let mut ops = vec![];
+        for (fname, f) in self.peek_ctx().get_builtins() {
+            if ["<", "<=", "distinct"].contains(&fname.as_str()) {
+                continue
+            }
+            for rsort in &sorts {
+                for ps in f.instances(rsort, &sorts) {
+                    ops.push((Symbol::new(fname.as_str()), ps, rsort.clone()));
+                }
+            }
+        }
+        for rsort in &sorts {
+            if let Sort::Datatype(dname) = rsort {
+                for (cname, fields) in self.peek_ctx().get_datatype(dname).expect("datatype must be declared") {
+                    if !fields.is_empty() {
+                        ops.push((Symbol::new(cname.as_str()), fields.iter().map(|(_, s)| s.clone()).collect(), rsort.clone()));
+                    }
+                }
+            }
+        }
+        ops.extend(self.components(leafs).into_iter().map(|(cname, psorts, csort)| (Symbol::Func(cname), psorts, csort)));
+        if self.get_config().recursive {
+            let name = self.get_synth().expect("there must be a function to synthesize");
+            let (params, rsort) = self.peek_ctx().get_decl(&name).expect("synth has to have decl");
+            ops.push((Symbol::Func(name.clone()), params.iter().map(|(_, s)| s.clone()).collect(), rsort.clone()));
+        }
+        for (dsort, ctors) in self.input_datatypes(leafs) {
+            for (cname, fields) in ctors {
+                ops.push((Symbol::Func(format!("is-{}", cname)), vec![dsort.clone()], Sort::Bool));
+                for (sname, fsort) in fields {
+                    ops.push((Symbol::new(sname.as_str()), vec![dsort.clone()], fsort.clone()));
+                }
+            }
+        }
+        ops
+    }
+
+    // add every term of the next size to the bank, returning the ones of sort rsort
+    fn grow(&self, bank: &mut Bank, leafs: &[(String, Sort)], rsort: &Sort) -> Option<Vec<Arc<Term>>> {
+        let mut terms: BTreeMap<Sort, Vec<Arc<Term>>> = BTreeMap::new();
+        if bank.terms.is_empty() {
+            for s in self.grammar_sorts(leafs) {
+                let ls = self.expand_nt(&"leafs".to_owned(), &s, &leafs.to_vec());
+                terms.insert(s, ls);
+            }
+        } else {
+            let size = bank.terms.len() + 1;
+            for (f, ps, r) in &bank.ops {
+                // split what is left of the size between the arguments in every way
+                for sizes in splits(size - 1, ps.len()) {
+                    let mut choices: Vec<Vec<Arc<Term>>> = vec![vec![]];
+                    for (s, n) in ps.iter().zip(sizes) {
+                        let args = match bank.terms[n - 1].get(s) {
+                            Some(args) => args,
+                            None => {
+                                choices.clear();
+                                break
+                            }
+                        };
+                        choices = choices.into_iter()
+                            .flat_map(|c| args.iter().map(move |a| {
+                                let mut c = c.clone();
+                                c.push(Arc::clone(a));
+                                c
+                            }))
+                            .collect();
+                    }
+                    let built = terms.entry(r.clone()).or_default();
+                    for args in choices {
+                        let t = Term::mk_app(f.clone(), args);
+                        if in_order(self.peek_ctx(), &t) {
+                            built.push(t);
+                        }
+                    }
+                }
+            }
+        }
+        let found = terms.get(rsort).cloned();
+        bank.terms.push(terms);
+        found
+    }
+
+    // the defined functions to build terms with, those of sorts we enumerate: their names, the sorts
+    // they take and the sort they give
+    fn components(&self, leafs: &[(String, Sort)]) -> Vec<(String, Vec<Sort>, Sort)> {
+        let sorts = self.grammar_sorts(leafs);
+        let mut components = vec![];
+        for cname in self.get_components() {
+            let (params, csort) = self.peek_ctx().get_decl(cname).expect("components are declared");
+            let psorts: Vec<Sort> = params.iter().map(|(_, s)| s.clone()).collect();
+            if sorts.contains(csort) && psorts.iter().all(|s| sorts.contains(s)) {
+                components.push((cname.clone(), psorts, csort.clone()));
+            }
+        }
+        components
+    }
+
+    // the sorts we are willing to enumerate terms of
+    fn grammar_sorts(&self, leafs: &[(String, Sort)]) -> Vec<Sort> {
+        let mut sorts = vec![Sort::Bool];
+        if self.peek_ctx().get_logic().lia {
+            sorts.push(Sort::Int);
+        }
+        if self.peek_ctx().get_logic().s {
+            sorts.push(Sort::String);
+        }
+        let name = self.get_synth().expect("there must be a function to synthesize");
+        let (_, rsort) = self.peek_ctx().get_decl(name.as_str()).expect("synth has to have decl");
+        for s in leafs.iter().map(|(_, s)| s).chain(std::iter::once(rsort)) {
+            if !sorts.contains(s) {
+                sorts.push(s.clone());
+            }
+        }
+        sorts
+    }
+
+    // the datatypes we can take apart: the ones the function to synthesize receives
+    fn input_datatypes(&self, leafs: &[(String, Sort)]) -> Vec<(Sort, &Vec<Constructor>)> {
+        let mut inputs: Vec<(Sort, &Vec<Constructor>)> = vec![];
+        for (_, isort) in leafs {
+            if let Sort::Datatype(dname) = isort {
+                if inputs.iter().all(|(s, _)| s != isort) {
+                    inputs.push((isort.clone(), self.peek_ctx().get_datatype(dname).expect("datatype must be declared")));
+                }
+            }
+        }
+        inputs
+    }
+}
+
+// every way of writing n as the sum of k positive numbers
+fn splits(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return if n == 0 {vec![vec![]]} else {vec![]}
+    }
+    let mut all = vec![];
+    for first in 1..=n {
+        for mut rest in splits(n - first, k - 1) {
+            rest.insert(0, first);
+            all.push(rest);
+        }
+    }
+    all
+}
+
+// the instances of interpreted symbols covered by the hand-tuned non-terminals above,
+// or that are just mirror images of them (e.g. < and >)
+fn hand_tuned(name: &str, params: &[Sort]) -> bool {
+    match name {
+        "=" => params[0] == Sort::Bool || params[0] == Sort::Int,
+        "ite" => params[1] == Sort::Bool || params[1] == Sort::Int,
+        "and" | "or" | "=>" | "not" | "xor" | "distinct"
+        | "+" | "-" | "*" | ">" | ">=" | "<" | "<=" => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::qry::Query;
+    use super::{Budget, Clock, Limit, Outcome};
+
+    #[test]
+    fn test_max_candidates() {
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        query.set_budget(Budget { max_candidates: Some(3), ..Budget::default() });
+        match query.solve() {
+            Outcome::Exhausted(Limit::Candidates, partial) => {
+                assert!(partial.candidates == 3);
+                assert!(partial.iterations == 0);
+            }
+            _ => panic!("expected to run out of candidates")
+        }
+        let stats = query.get_stats();
+        assert!(stats.candidates == 3);
+        assert!(stats.solver_calls == 0);
+        assert!(stats.total >= stats.enumeration + stats.verification);
+    }
+
+    #[test]
+    fn test_bottom_up() {
+        use super::{Config, Strategy};
+
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        query.set_config(Config { strategy: Strategy::BottomUp, ..Config::default() });
+        // the first complete terms come straight from the leafs, before any call to the solver
+        query.set_budget(Budget { max_iterations: Some(0), ..Budget::default() });
+        match query.solve() {
+            Outcome::Exhausted(Limit::Iterations, partial) => {
+                assert!(partial.iterations == 0);
+                assert!(partial.candidates > 0);
+            }
+            _ => panic!("expected to run out of iterations")
+        }
+        assert!(Config::all().iter().map(|c| c.to_string()).collect::<Vec<String>>()
+                == vec!["top-down/cvc4", "top-down/z3", "bottom-up/cvc4", "bottom-up/z3"]);
+    }
+
+    #[test]
+    fn test_warm_start() {
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        let unparsed_cexs = fs::read_to_string("tests/data/simple_cex.smt2").expect("cannot read file");
+        let cexs = query.parse_counterexamples(&unparsed_cexs).expect("cannot parse counterexamples");
+        query.set_counterexamples(cexs);
+        // h returns a Bool
+        let hints = query.parse_terms("(+ x 1) (and x y)").expect("cannot parse hints");
+        for h in hints {
+            assert!(query.set_hints(vec![h]).is_err());
+        }
+        let hints = query.parse_terms("false").expect("cannot parse hints");
+        query.set_hints(hints).expect("hints must type check");
+
+        // the seed rules out the hint without asking the solver
+        query.set_budget(Budget { max_candidates: Some(1), ..Budget::default() });
+        match query.solve() {
+            Outcome::Exhausted(Limit::Candidates, partial) => {
+                assert!(partial.candidates == 1);
+                assert!(partial.iterations == 0);
+                assert!(partial.best.is_none());
+            }
+            _ => panic!("expected to run out of candidates")
+        }
+        // the duplicate seed and the one that is missing values are dropped
+        assert!(query.get_counterexamples().len() == 1);
+    }
+
+    #[test]
+    fn test_classes() {
+        use std::sync::Arc;
+        use crate::ctx::Sort;
+        use super::Classes;
+
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        let params = vec![("x".to_owned(), Sort::Int), ("y".to_owned(), Sort::Int)];
+        let mut classes = Classes::new(&params, query.call_sites("h"));
+        classes.update(query.peek_ctx(), &[]);
+        assert!(classes.points.len() == 16);
+
+        let terms = query.parse_terms("(>= x y) (not (< x y)) (> x y)").expect("cannot parse terms");
+        let sigs: Vec<_> = terms.iter().map(|t| classes.signature(t)).collect();
+        assert!(sigs[0] == sigs[1]);
+        assert!(sigs[0] != sigs[2]);
+
+        // a counterexample with new values gives new points
+        let cex = query.parse_answer("sat\n(model\n(define-fun initial_a () Int 7)\n)").expect("cannot parse answer");
+        classes.update(query.peek_ctx(), &[cex]);
+        assert!(classes.points.len() == 25);
+
+        // once we know (not (< x y)) is (>= x y), every term with it in is redundant
+        classes.rules.insert(Arc::clone(&terms[1]), Arc::clone(&terms[0]));
+        assert!(classes.redundant(&terms[1]));
+        assert!(!classes.redundant(&terms[0]));
+        let bigger = query.parse_terms("(and (not (< x y)) true)").expect("cannot parse terms");
+        assert!(classes.redundant(&bigger[0]));
+
+        // a parameter may share its name with a constant
+        let mut query = Query::new();
+        query.parse_query("(set-logic QF_UFLIA)(synth-blocking-fun f ((x Int)) Bool)(declare-const x Int)(assert (f x))").expect("cannot parse query");
+        let params = vec![("x".to_owned(), Sort::Int)];
+        let mut classes = Classes::new(&params, query.call_sites("f"));
+        let cex = query.parse_answer("sat\n(model\n(define-fun x () Int 3)\n)").expect("cannot parse answer");
+        classes.update(query.peek_ctx(), &[cex]);
+        assert!(!classes.points.is_empty());
+        let terms = query.parse_terms("(>= x 3) (> x 2)").expect("cannot parse terms");
+        assert!(classes.signature(&terms[0]) == classes.signature(&terms[1]));
+    }
+
+    #[test]
+    fn test_in_order() {
+        use std::sync::Arc;
+        use crate::ast::{Term, Symbol};
+        use crate::ctx::Sort;
+        use super::{in_order, pairs};
+
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        let ctx = query.peek_ctx();
+
+        let kept = query.parse_terms("(+ 1 x) (+ x x) (+ (+ 1 x) y) (>= y x) (- x 1) (and (> x y) (>= x y))").expect("cannot parse terms");
+        assert!(kept.iter().all(|t| in_order(ctx, t)));
+        let dropped = query.parse_terms("(+ x 1) (+ 0 x) (* x 1) (+ x (+ 1 y)) (+ (+ 1 y) x) (= y x) (and (> x y) (> x y)) (not (> (+ x 1) y))").expect("cannot parse terms");
+        assert!(dropped.iter().all(|t| !in_order(ctx, t)));
+
+        // an argument that is still a hole may turn out to be in order, whichever side it is on
+        let op = Term::mk_const(Symbol::NonTerm(Sort::Int, "op".to_owned()));
+        let x = Term::mk_const(Symbol::new("x"));
+        assert!(in_order(ctx, &Term::mk_app(Symbol::new("+"), vec![Arc::clone(&op), Arc::clone(&x)])));
+        assert!(in_order(ctx, &Term::mk_app(Symbol::new("+"), vec![Arc::clone(&x), Arc::clone(&op)])));
+
+        // so both orders are generated, and two holes together too
+        let order = vec![Term::mk_const(Symbol::IntLit(1)), x, op];
+        let sums: Vec<String> = pairs(ctx, "+", &order, false).iter().map(|t| t.to_string()).collect();
+        assert!(sums.len() == 6);
+        assert!(!sums.contains(&"(+ x 1)".to_owned()));
+    }
+
+    #[test]
+    fn test_constants() {
+        use crate::ast::{Term, Symbol};
+        use crate::ctx::Sort;
+        use super::Config;
+
+        let unparsed_query = "(set-logic QF_LIA)\n(synth-blocking-fun f ((x Int)) Bool)\n(declare-const x Int)\n(assert (not (= (f x) (>= x 100))))\n(check-sat)";
+        let mut query = Query::new();
+        query.parse_query(unparsed_query).expect("cannot parse query");
+        let constants: Vec<String> = query.int_constants().iter().map(|t| t.to_string()).collect();
+        assert!(constants == ["0", "1", "100"]);
+
+        query.set_config(Config { symbolic: true, ..Config::default() });
+        assert!(query.int_constants().len() == 4);
+
+        // each counterexample gets its own x, and the sketch goes into the spec
+        let sketch = Term::mk_app(Symbol::new(">="), vec![Term::mk_const(Symbol::new("x")), Term::mk_const(Symbol::NonTerm(Sort::Int, "const".to_owned()))]);
+        let cex = query.parse_answer("sat\n(model\n(define-fun x () Int (- 3))\n)").expect("cannot parse answer");
+        let text = query.constant_query("f", &sketch, &[cex]).to_string();
+        assert!(text.contains("(declare-const c!0 Int)"));
+        assert!(text.contains("(define-fun x!0 () Int (- 3))"));
+        assert!(text.contains("(assert (not (not (= (>= x!0 c!0) (>= x!0 100)))))"));
+    }
+
+    #[test]
+    fn test_terminates() {
+        use std::sync::Arc;
+        use super::{terminates, unguardable};
+
+        let unparsed_query = "(set-logic QF_UFLIA)\n(synth-blocking-fun f ((x Int)) Bool)\n(declare-const x Int)\n(assert (not (and (f 0) (=> (> x 0) (= (f x) (not (f (- x 1))))) (=> (< x 0) (f x)))))\n(check-sat)";
+        let mut query = Query::new();
+        query.parse_query(unparsed_query).expect("cannot parse query");
+        let (params, _) = query.peek_ctx().get_decl("f").expect("f is declared").clone();
+        let ctx = query.peek_ctx();
+
+        let kept = query.parse_terms("(ite (>= 0 x) true (not (f (- x 1)))) (or (> 1 x) (not (f (- x 2)))) (=> (> x 0) (not (f (- x 1)))) (>= x 3)").expect("cannot parse terms");
+        assert!(kept.iter().all(|t| terminates(ctx, "f", &params, t, &mut vec![])));
+        // unguarded, guarded the wrong way, or not getting smaller
+        let dropped = query.parse_terms("(not (f (- x 1))) (ite (>= x 0) true (f (- x 1))) (and (> x 0) (f (+ x 1))) (or (> x 0) (f (- x 1)))").expect("cannot parse terms");
+        assert!(dropped.iter().all(|t| !terminates(ctx, "f", &params, t, &mut vec![])));
+        assert!(unguardable(ctx, "f", &params, &dropped[2]));
+        assert!(!unguardable(ctx, "f", &params, &dropped[0]));
+
+        // checked with a recursive definition, which only a logic with quantifiers has
+        query.add_body("f", Arc::clone(&kept[0]));
+        let text = query.to_string();
+        assert!(text.starts_with("(set-logic UFLIA)"));
+        assert!(text.contains("(define-fun-rec f ((x Int)) Bool (ite (>= 0 x) true (not (f (- x 1)))))"));
+        let cex = query.parse_answer("sat\n(model\n(define-fun x () Int 7)\n)").expect("cannot parse answer");
+        assert!(query.eval(&cex) == Some(false));
+        query.remove_body("f");
+
+        // with two parameters every call must get smaller in the same order: x first, then y
+        let mut two = Query::new();
+        two.parse_query("(set-logic QF_UFLIA)\n(synth-blocking-fun g ((x Int) (y Int)) Int)\n(check-sat)").expect("cannot parse query");
+        let (params, _) = two.peek_ctx().get_decl("g").expect("g is declared").clone();
+        let ctx = two.peek_ctx();
+        let kept = two.parse_terms("(ite (> x 0) (g (- x 1) 7) 0) (ite (> x 0) (g (- x 1) (+ y 1)) (ite (> y 0) (g x (- y 1)) 0))").expect("cannot parse terms");
+        assert!(kept.iter().all(|t| terminates(ctx, "g", &params, t, &mut vec![])));
+        // each call makes one of them smaller, but the other bigger
+        let dropped = two.parse_terms("(ite (> x 0) (g (- x 1) (+ y 1)) (ite (> y 0) (g (+ x 5) (- y 1)) 0))").expect("cannot parse terms");
+        assert!(dropped.iter().all(|t| !terminates(ctx, "g", &params, t, &mut vec![])));
+    }
+
+    #[test]
+    fn test_components() {
+        use crate::ctx::Sort;
+
+        let unparsed_query = "(set-logic QF_UFLIA)\n(define-fun max2 ((a Int) (b Int)) Int (ite (>= a b) a b))\n(define-fun pos ((a Int)) Bool (> a 0))\n(synth-blocking-fun f ((x Int)) Int)\n(declare-const x Int)\n(assert (not (= (f x) (max2 x 3))))\n(check-sat)";
+        let mut query = Query::new();
+        query.parse_query(unparsed_query).expect("cannot parse query");
+        assert!(query.get_defined() == ["max2", "pos"]);
+        let params = vec![("x".to_owned(), Sort::Int)];
+        let expand = |query: &Query, nt: &str, rsort: &Sort| -> Vec<String> {
+            query.expand_nt(&nt.to_owned(), rsort, &params).iter().map(|t| t.to_string()).collect()
+        };
+        assert!(!expand(&query, "op", &Sort::Int).contains(&"?component?".to_owned()));
+
+        // only the ones named, at their own sorts, top down and bottom up
+        assert!(query.set_components(vec!["min2".to_owned()]).is_err());
+        query.set_components(vec!["max2".to_owned()]).expect("max2 is defined");
+        assert!(expand(&query, "op", &Sort::Int).contains(&"?component?".to_owned()));
+        assert!(!expand(&query, "op", &Sort::Bool).contains(&"?component?".to_owned()));
+        assert!(expand(&query, "component", &Sort::Int) == ["(max2 ?start? ?start?)"]);
+        let ops: Vec<String> = query.grammar_ops(&params).iter().map(|(f, _, _)| f.to_string()).collect();
+        assert!(ops.contains(&"max2".to_owned()) && !ops.contains(&"pos".to_owned()));
+
+        // and evaluated with their definitions
+        let body = query.parse_terms("(max2 3 x)").expect("cannot parse terms").remove(0);
+        let cex = query.parse_answer("sat\n(model\n(define-fun x () Int 5)\n)").expect("cannot parse answer");
+        assert!(!query.fails("f", &body, &[cex]));
+    }
+
+    #[test]
+    fn test_explain() {
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        let cex = query.parse_answer("sat\n(model\n(define-fun initial_a () Int 2)\n(define-fun initial_b () Int 3)\n(define-fun new_a () Int 3)\n(define-fun new_b () Int 5)\n)").expect("cannot parse answer");
+        let bodies = query.parse_terms("false (< x 3)").expect("cannot parse terms");
+
+        // (h 0 1) alone rules out false
+        let e = query.explain("h", &bodies[0], &cex, 0);
+        assert!(e.model.is_empty());
+        assert!(e.to_string() == "false fails anywhere, where (h 0 1) is (h 0 1) = false");
+
+        // but it takes all of it to rule out (< x 3)
+        let e = query.explain("h", &bodies[1], &cex, 1);
+        assert!(e.model.len() == 4);
+        let values: Vec<String> = e.sites.iter().map(|(_, call, v)| format!("{} = {}", call, v)).collect();
+        assert!(values == ["(h 0 1) = true", "(h 2 3) = true", "(h 3 5) = false"]);
+    }
+
+    #[test]
+    fn test_call_sites() {
+        use crate::ctx::Sort;
+        use super::Classes;
+
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        let sites = query.call_sites("h");
+        let shown: Vec<String> = sites.iter().map(|(s, needs)| format!("{} {:?}", s, needs)).collect();
+        assert!(shown == ["(h 0 1) []", "(h initial_a initial_b) [\"initial_a\", \"initial_b\"]", "(h new_a new_b) [\"new_a\", \"new_b\"]"]);
+
+        let cex = query.parse_answer("sat\n(model\n(define-fun initial_a () Int 2)\n(define-fun initial_b () Int 3)\n(define-fun new_a () Int 3)\n(define-fun new_b () Int 5)\n)").expect("cannot parse answer");
+        assert!(query.constrained("h", &cex) == ["(h 0 1) at (h 0 1)", "(h initial_a initial_b) at (h 2 3)", "(h new_a new_b) at (h 3 5)"]);
+
+        // the inputs the spec calls h at are the first sample inputs
+        let params = vec![("x".to_owned(), Sort::Int), ("y".to_owned(), Sort::Int)];
+        let mut classes = Classes::new(&params, sites);
+        classes.update(query.peek_ctx(), &[cex]);
+        let x = query.parse_terms("x").expect("cannot parse terms");
+        let xs: Vec<String> = classes.signature(&x[0]).iter().take(3).map(|v| v.to_string()).collect();
+        assert!(xs == ["0", "2", "3"]);
+    }
+
+    #[test]
+    fn test_max_iterations() {
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        // stop before the first call to the solver
+        query.set_budget(Budget { max_iterations: Some(0), ..Budget::default() });
+        match query.solve() {
+            Outcome::Exhausted(Limit::Iterations, partial) => {
+                assert!(partial.iterations == 0);
+                assert!(partial.best.is_none());
+                assert!(partial.counterexamples.is_empty());
+            }
+            _ => panic!("expected to run out of iterations")
+        }
+        // the candidate under test is not left behind
+        assert!(query.peek_ctx().get_body("h").is_none());
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        use std::sync::Arc;
+        use crate::ast::{Term, Symbol};
+        use crate::ctx::Sort;
+
+        let unparsed_query = fs::read_to_string("tests/data/simple.synth").expect("cannot read file");
+        let mut query = Query::new();
+        query.parse_query(&unparsed_query).expect("cannot parse file");
+        let ctx = query.parse_answer("sat\n(model\n(define-fun initial_b () Int 0)\n(define-fun initial_a () Int 0)\n(define-fun new_a () Int 0)\n(define-fun new_b () Int 0)\n)").expect("cannot parse answer");
+        let ctxs = vec! [ctx];
+
+        // however the levels are split up, they come back the same
+        let clock = Clock { deadline: None, stop: None };
+        let rules = std::collections::BTreeMap::new();
+        let mut level = vec! [Term::new(Symbol::NonTerm(Sort::Bool, "start".to_owned()), vec! [])];
+        for _ in 0..5 {
+            let (next, complete, _) = query.search_level("h", &level, &ctxs, &rules, 1, &clock).expect("no deadline");
+            for threads in [2, 3, 8] {
+                let (pnext, pcomplete, _) = query.search_level("h", &level, &ctxs, &rules, threads, &clock).expect("no deadline");
+                let show = |ts: &Vec<Arc<Term>>| ts.iter().map(|t| t.to_string()).collect::<Vec<String>>();
+                assert!(show(&next) == show(&pnext));
+                assert!(show(&complete) == show(&pcomplete));
+            }
+            level = next;
+        }
+        assert!(level.len() > 100);
+    }
+}
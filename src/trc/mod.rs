@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::ast::Term;
+use crate::smt::SolverError;
+
+// a record of what solve did, one json object per line:
+// {"event": "start", "config": c}                    a call to solve
+// {"event": "candidate", "term": t}                  a candidate taken off the search
+// {"event": "killed", "term": t, "counterexample": i} the counterexample that ruled it out
+// {"event": "query", "id": n, "term": t, "text": q}  a complete candidate sent to the solver
+// {"event": "answer", "id": n, "text": a}            what the solver said
+// {"event": "timeout", "id": n}                      or that it did not say anything in time
+// {"event": "counterexample", "index": i, "id": n, "sites": s, "term": t, "model": m, "values": v}
+//                                                    the answer to query n was a new counterexample,
+//                                                    s the calls to the function it constrains, t the
+//                                                    candidate it refutes, m the part of it that matters
+//                                                    and v what t gives at the calls m pins down
+// {"event": "done", "status": s}
+#[derive(Clone)]
+pub struct Recorder {
+    out: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn new(path: &str) -> Recorder {
+        let file = File::create(path).expect("cannot create trace file");
+        Recorder { out: Arc::new(Mutex::new(BufWriter::new(file))) }
+    }
+
+    fn write(&self, fields: &[(&str, String)], flush: bool) {
+        let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{}: {}", quote(k), v)).collect();
+        let mut out = self.out.lock().expect("trace poisoned");
+        writeln!(out, "{{{}}}", fields.join(", ")).expect("cannot write trace");
+        // everything up to the last solver call survives the run being killed
+        if flush {
+            out.flush().expect("cannot write trace");
+        }
+    }
+
+    pub fn start(&self, config: &str) {
+        self.write(&[("event", quote("start")), ("config", quote(config))], true);
+    }
+
+    pub fn candidate(&self, term: &Term) {
+        self.write(&[("event", quote("candidate")), ("term", quote(&term.to_string()))], false);
+    }
+
+    pub fn killed(&self, term: &Term, counterexample: usize) {
+        self.write(&[("event", quote("killed")), ("term", quote(&term.to_string())), ("counterexample", counterexample.to_string())], false);
+    }
+
+    pub fn query(&self, id: usize, term: &Term, text: &str) {
+        self.write(&[("event", quote("query")), ("id", id.to_string()), ("term", quote(&term.to_string())), ("text", quote(text))], true);
+    }
+
+    pub fn answer(&self, id: usize, answer: &Result<String, SolverError>) {
+        match answer {
+            Ok(text) => self.write(&[("event", quote("answer")), ("id", id.to_string()), ("text", quote(text))], true),
+            Err(SolverError::Timeout) => self.write(&[("event", quote("timeout")), ("id", id.to_string())], true),
+            Err(e) => self.write(&[("event", quote("error")), ("id", id.to_string()), ("text", quote(&e.to_string()))], true),
+        }
+    }
+
+    pub fn counterexample(&self, index: usize, id: usize, sites: &[String], term: &Term, model: &str, values: &[String]) {
+        let sites = quote(&sites.join("; "));
+        let values = quote(&values.join("; "));
+        self.write(&[("event", quote("counterexample")), ("index", index.to_string()), ("id", id.to_string()), ("sites", sites),
+            ("term", quote(&term.to_string())), ("model", quote(model)), ("values", values)], false);
+    }
+
+    pub fn done(&self, status: &str) {
+        self.write(&[("event", quote("done")), ("status", quote(status))], true);
+    }
+}
+
+// the solver answers in a trace, by query text, to stand in for the solver
+pub struct Replay {
+    // none if the solver timed out
+    answers: HashMap<String, Option<String>>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Replay {
+        let trace = fs::read_to_string(path).expect("cannot read trace file");
+        let mut queries = HashMap::new();
+        let mut answers = HashMap::new();
+        for line in trace.lines() {
+            let fields = fields(line).unwrap_or_else(|| panic!("bad trace line: {}", line));
+            let id = || fields.get("id").expect("trace event must have an id").clone();
+            match fields.get("event").map(|e| e.as_str()) {
+                Some("query") => {
+                    queries.insert(id(), fields.get("text").expect("query must have text").clone());
+                }
+                Some("answer") => {
+                    let query = queries.get(&id()).expect("answer to an unknown query");
+                    answers.insert(query.clone(), Some(fields.get("text").expect("answer must have text").clone()));
+                }
+                Some("timeout") => {
+                    let query = queries.get(&id()).expect("answer to an unknown query");
+                    answers.insert(query.clone(), None);
+                }
+                _ => ()
+            }
+        }
+        Replay { answers }
+    }
+
+    pub fn answer(&self, text: &str) -> Result<String, SolverError> {
+        match self.answers.get(text) {
+            Some(Some(answer)) => Ok(answer.clone()),
+            Some(None) => Err(SolverError::Timeout),
+            None => Err(SolverError::Failed("query is not in the trace".to_owned())),
+        }
+    }
+}
+
+// a json string literal
+pub fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// the fields of a flat json object whose values are strings or numbers, as written by a Recorder
+fn fields(line: &str) -> Option<HashMap<String, String>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    let skip = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    };
+    if chars.next()? != '{' {
+        return None
+    }
+    loop {
+        skip(&mut chars);
+        match chars.peek()? {
+            '}' => return Some(fields),
+            ',' => { chars.next(); skip(&mut chars); }
+            _ => ()
+        }
+        let key = unquote(&mut chars)?;
+        skip(&mut chars);
+        if chars.next()? != ':' {
+            return None
+        }
+        skip(&mut chars);
+        let value = if *chars.peek()? == '"' {
+            unquote(&mut chars)?
+        } else {
+            let mut v = String::new();
+            while chars.peek().is_some_and(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '-') {
+                v.push(chars.next()?);
+            }
+            v
+        };
+        fields.insert(key, value);
+    }
+}
+
+fn unquote(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None
+    }
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'u' => {
+                    let code: String = (0..4).map(|_| chars.next()).collect::<Option<String>>()?;
+                    out.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    use crate::ast::{Term, Symbol};
+    use crate::smt::SolverError;
+    use super::{Recorder, Replay, fields, quote};
+
+    #[test]
+    fn test_quote() {
+        let line = format!("{{\"event\": {}, \"id\": 3}}", quote("say \"hi\"\n\t\\ \u{1}"));
+        let fields = fields(&line).expect("cannot read line");
+        assert!(fields["event"] == "say \"hi\"\n\t\\ \u{1}");
+        assert!(fields["id"] == "3");
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut path = env::temp_dir();
+        path.push(format!("llama-{}-trace.jsonl", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+
+        let t: Arc<Term> = Term::mk_app(Symbol::new("x"), vec![]);
+        let trace = Recorder::new(&path);
+        trace.start("top-down/cvc4");
+        trace.query(0, &t, "(check-sat)\n");
+        trace.answer(0, &Ok("sat\n(model)\n".to_owned()));
+        trace.query(1, &t, "(assert false)\n(check-sat)\n");
+        trace.answer(1, &Err(SolverError::Timeout));
+        trace.done("timeout");
+        drop(trace);
+
+        let replay = Replay::load(&path);
+        fs::remove_file(&path).ok();
+        assert!(replay.answer("(check-sat)\n").expect("recorded answer") == "sat\n(model)\n");
+        match replay.answer("(assert false)\n(check-sat)\n") {
+            Err(SolverError::Timeout) => (),
+            _ => panic!("expected a recorded timeout")
+        }
+        match replay.answer("(get-model)") {
+            Err(SolverError::Failed(_)) => (),
+            _ => panic!("expected the query to be missing")
+        }
+    }
+}
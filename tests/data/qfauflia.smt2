@@ -0,0 +1,7 @@
+(set-logic QF_AUFLIA)
+(declare-const a (Array Int Int))
+(declare-const i Int)
+(assert (= (select (store a i 5) i) (+ (select a 0) 5)))
+(assert (> i 0))
+(check-sat)
+(get-model)
\ No newline at end of file
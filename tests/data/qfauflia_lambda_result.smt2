@@ -0,0 +1,5 @@
+sat
+(model
+(define-fun a () (Array Int Int) (lambda ((x Int)) (ite (= x 1) 3 (ite (= 2 x) 4 0))))
+(define-fun i () Int 1)
+)
\ No newline at end of file
@@ -0,0 +1,5 @@
+sat
+(model
+(define-fun a () (Array Int Int) (store ((as const (Array Int Int)) 0) 1 3))
+(define-fun i () Int 1)
+)
\ No newline at end of file
@@ -0,0 +1,6 @@
+(set-logic QF_UFDTLIA)
+(declare-datatype Msg ((ping (seq Int)) (pong (ack Int) (body Msg)) (done)))
+(declare-const m Msg)
+(assert (and (is-pong m) (= (ack m) 3) (= (seq (body m)) 4)))
+(check-sat)
+(get-model)
\ No newline at end of file
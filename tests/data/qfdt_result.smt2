@@ -0,0 +1,4 @@
+sat
+(model
+(define-fun m () Msg (pong 3 (ping 4)))
+)
\ No newline at end of file
@@ -0,0 +1,9 @@
+(set-logic QF_SLIA)
+(declare-const x String)
+(declare-const y String)
+(assert (= (str.++ x "-" y) "ab-c"))
+(assert (= (str.len x) 2))
+(assert (str.prefixof "a" (str.at x 0)))
+(assert (= (str.indexof (str.replace "a""bc" "b" "") "c" 0) 2))
+(check-sat)
+(get-model)
\ No newline at end of file
@@ -0,0 +1,5 @@
+sat
+(model
+(define-fun x () String "ab")
+(define-fun y () String "c")
+)
\ No newline at end of file
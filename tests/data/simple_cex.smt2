@@ -0,0 +1,18 @@
+(model
+  (define-fun initial_a () Int 0)
+  (define-fun initial_b () Int 1)
+  (define-fun new_a () Int 1)
+  (define-fun new_b () Int 1)
+)
+sat
+(
+  (define-fun initial_a () Int 0)
+  (define-fun initial_b () Int 1)
+  (define-fun new_a () Int 1)
+  (define-fun new_b () Int 1)
+  (define-fun old_c () Int 7)
+)
+(model
+  (define-fun initial_a () Int 2)
+  (define-fun new_a () Bool true)
+)
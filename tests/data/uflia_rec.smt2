@@ -0,0 +1,7 @@
+(set-logic UFLIA)
+(define-fun-rec sum ((n Int)) Int (ite (<= n 0) 0 (+ n (sum (- n 1)))))
+(define-funs-rec ((even ((n Int)) Bool) (odd ((n Int)) Bool)) ((ite (= n 0) true (odd (- n 1))) (ite (= n 0) false (even (- n 1)))))
+(declare-const x Int)
+(assert (= (sum x) 10))
+(assert (even x))
+(check-sat)
\ No newline at end of file